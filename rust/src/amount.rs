@@ -0,0 +1,198 @@
+// Conversions between `Amount`, BTC strings and satoshis
+// Everything goes through integer satoshis or decimal strings so no value is
+// ever rounded through an f64 on its way in or out.
+use crate::error::AppError;
+use bitcoincore_rpc::bitcoin::{Amount, Denomination, SignedAmount};
+use std::str::FromStr;
+
+const SATS_PER_BTC: u64 = 100_000_000;
+
+// Unit a user-supplied amount is written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Btc,
+    Sat,
+}
+
+impl FromStr for Unit {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "btc" => Ok(Unit::Btc),
+            "sat" | "sats" => Ok(Unit::Sat),
+            other => Err(AppError::Usage(format!(
+                "unknown unit '{other}' (expected btc or sat)"
+            ))),
+        }
+    }
+}
+
+// Parse a decimal amount in `unit`, rejecting negatives, sub-satoshi
+// precision and anything above the 21M BTC supply
+pub fn parse_amount(value: &str, unit: Unit) -> Result<Amount, AppError> {
+    let denomination = match unit {
+        Unit::Btc => Denomination::Bitcoin,
+        Unit::Sat => Denomination::Satoshi,
+    };
+    let amount = Amount::from_str_in(value.trim(), denomination)?;
+    if amount > Amount::MAX_MONEY {
+        return Err(AppError::Usage(format!(
+            "amount {value} exceeds the 21,000,000 BTC supply"
+        )));
+    }
+    Ok(amount)
+}
+
+// BTC with exactly eight decimals, e.g. "29.99998590"
+pub fn to_btc_string(amount: Amount) -> String {
+    let sats = amount.to_sat();
+    format!("{}.{:08}", sats / SATS_PER_BTC, sats % SATS_PER_BTC)
+}
+
+pub fn to_sat(amount: Amount) -> u64 {
+    amount.to_sat()
+}
+
+// Size of a signed wallet amount, e.g. the negative value of a send
+pub fn magnitude(amount: SignedAmount) -> Amount {
+    Amount::from_sat(amount.to_sat().unsigned_abs())
+}
+
+// An amount for an RPC argument. Core accepts decimal strings anywhere it
+// accepts numbers, which keeps the value exact on the wire.
+pub fn to_rpc_value(amount: Amount) -> serde_json::Value {
+    serde_json::Value::String(to_btc_string(amount))
+}
+
+// How amounts are shown in the printed summary. The report files always use
+// `to_btc_string` so they stay machine-readable whatever is chosen here.
+pub trait AmountFormatter: Send + Sync {
+    fn format(&self, amount: Amount) -> String;
+}
+
+// "1234.50000000", the same text the report files carry
+pub struct PlainFormatter;
+
+impl AmountFormatter for PlainFormatter {
+    fn format(&self, amount: Amount) -> String {
+        to_btc_string(amount)
+    }
+}
+
+// "1,234.50000000": thousands separators in the whole-BTC part
+pub struct GroupedFormatter;
+
+impl AmountFormatter for GroupedFormatter {
+    fn format(&self, amount: Amount) -> String {
+        let sats = amount.to_sat();
+        let whole = (sats / SATS_PER_BTC).to_string();
+        let mut grouped = String::with_capacity(whole.len() + whole.len() / 3);
+        for (i, digit) in whole.chars().enumerate() {
+            if i > 0 && (whole.len() - i).is_multiple_of(3) {
+                grouped.push(',');
+            }
+            grouped.push(digit);
+        }
+        format!("{grouped}.{:08}", sats % SATS_PER_BTC)
+    }
+}
+
+// Formatter choices for --display-format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DisplayFormat {
+    #[default]
+    Plain,
+    Grouped,
+}
+
+impl DisplayFormat {
+    pub fn formatter(self) -> &'static dyn AmountFormatter {
+        match self {
+            DisplayFormat::Plain => &PlainFormatter,
+            DisplayFormat::Grouped => &GroupedFormatter,
+        }
+    }
+}
+
+impl FromStr for DisplayFormat {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(DisplayFormat::Plain),
+            "grouped" => Ok(DisplayFormat::Grouped),
+            other => Err(AppError::Usage(format!(
+                "unknown display format '{other}' (expected plain or grouped)"
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_accepts_the_whole_supply() {
+        let amount = parse_amount("21000000", Unit::Btc).unwrap();
+        assert_eq!(amount, Amount::MAX_MONEY);
+    }
+
+    #[test]
+    fn parse_rejects_one_sat_over_the_supply() {
+        assert!(parse_amount("21000000.00000001", Unit::Btc).is_err());
+        assert!(parse_amount("2100000000000001", Unit::Sat).is_err());
+    }
+
+    #[test]
+    fn parse_rejects_negative_values() {
+        assert!(parse_amount("-1", Unit::Btc).is_err());
+        assert!(parse_amount("-0.00000001", Unit::Btc).is_err());
+        assert!(parse_amount("-5", Unit::Sat).is_err());
+    }
+
+    #[test]
+    fn single_sat_round_trips() {
+        let amount = parse_amount("0.00000001", Unit::Btc).unwrap();
+        assert_eq!(to_sat(amount), 1);
+        assert_eq!(to_btc_string(amount), "0.00000001");
+    }
+
+    #[test]
+    fn parse_rejects_sub_sat_precision() {
+        assert!(parse_amount("0.000000001", Unit::Btc).is_err());
+        assert!(parse_amount("1.5", Unit::Sat).is_err());
+    }
+
+    #[test]
+    fn parse_in_sats() {
+        let amount = parse_amount("2000000000", Unit::Sat).unwrap();
+        assert_eq!(amount, Amount::from_btc(20.0).unwrap());
+        assert_eq!(to_btc_string(amount), "20.00000000");
+        assert_eq!(parse_amount(" 1 ", Unit::Sat).unwrap(), Amount::from_sat(1));
+    }
+
+    #[test]
+    fn unit_names() {
+        assert_eq!("BTC".parse::<Unit>().unwrap(), Unit::Btc);
+        assert_eq!("sats".parse::<Unit>().unwrap(), Unit::Sat);
+        assert!("msat".parse::<Unit>().is_err());
+    }
+
+    #[test]
+    fn grouped_formatter_separates_thousands() {
+        let format = |sats| GroupedFormatter.format(Amount::from_sat(sats));
+        assert_eq!(format(0), "0.00000000");
+        assert_eq!(format(1), "0.00000001");
+        assert_eq!(format(99_900_000_000), "999.00000000");
+        assert_eq!(format(123_450_000_000), "1,234.50000000");
+        assert_eq!(format(Amount::MAX_MONEY.to_sat()), "21,000,000.00000000");
+    }
+
+    #[test]
+    fn plain_formatter_matches_the_report_text() {
+        let amount = Amount::from_sat(123_450_000_000);
+        assert_eq!(PlainFormatter.format(amount), "1234.50000000");
+    }
+}
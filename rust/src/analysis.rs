@@ -0,0 +1,882 @@
+// Transaction analysis: resolving what each input spent
+use crate::amount;
+use crate::error::AppError;
+use crate::fees::ConfirmationEstimate;
+use crate::logging::info;
+use crate::report::TransactionReport;
+use bitcoincore_rpc::bitcoin::hashes::Hash;
+use bitcoincore_rpc::bitcoin::script::Instruction;
+use bitcoincore_rpc::bitcoin::{Amount, Block, BlockHash, Script, Txid};
+use bitcoincore_rpc::json::{
+    DecodeRawTransactionResult, GetBlockStatsResultPartial, GetRawTransactionResultVin,
+    GetRawTransactionResultVinScriptSig, GetRawTransactionResultVoutScriptPubKey, ScriptPubkeyType,
+};
+use bitcoincore_rpc::{Client, RpcApi};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Regtest halves the block subsidy every 150 blocks (mainnet: 210,000)
+pub const REGTEST_HALVING_INTERVAL: u64 = 150;
+
+// Interval block_subsidy uses, for nodes run with -subsidyhalvinginterval
+static HALVING_INTERVAL: AtomicU64 = AtomicU64::new(REGTEST_HALVING_INTERVAL);
+
+pub fn set_halving_interval(blocks: u64) {
+    HALVING_INTERVAL.store(blocks.max(1), Ordering::Relaxed);
+}
+
+pub fn halving_interval() -> u64 {
+    HALVING_INTERVAL.load(Ordering::Relaxed)
+}
+
+// Subsidy of a regtest block at `height`, starting from 50 BTC
+pub fn block_subsidy(height: u64) -> Amount {
+    let halvings = height / halving_interval();
+    if halvings >= 64 {
+        return Amount::ZERO;
+    }
+    Amount::from_sat(Amount::from_int_btc(50).to_sat() >> halvings)
+}
+
+// The subsidy block_subsidy expects next to the one the coinbase claimed
+#[derive(Debug, Clone, Copy)]
+pub struct SubsidyCheck {
+    pub configured: Amount,
+    /// Coinbase outputs minus the fees of the block's other transactions
+    pub observed: Amount,
+}
+
+impl SubsidyCheck {
+    pub fn matches(&self) -> bool {
+        self.configured == self.observed
+    }
+}
+
+// Compare the configured halving schedule with what the block's coinbase
+// actually claimed, which follows the node's real -subsidyhalvinginterval
+pub fn check_subsidy(rpc: &Client, block: &Block, height: u64) -> Result<SubsidyCheck, AppError> {
+    let block_hash = block.block_hash();
+    let coinbase = block
+        .txdata
+        .first()
+        .ok_or_else(|| AppError::Decode(format!("block {block_hash} has no coinbase")))?;
+    let claimed: Amount = coinbase.output.iter().map(|o| o.value).sum();
+    let stats: GetBlockStatsResultPartial =
+        rpc.call("getblockstats", &[json!(block_hash), json!(["totalfee"])])?;
+    let fees = stats
+        .total_fee
+        .ok_or_else(|| AppError::Decode("getblockstats omitted totalfee".to_string()))?;
+    Ok(SubsidyCheck {
+        configured: block_subsidy(height),
+        observed: claimed.checked_sub(fees).unwrap_or(Amount::ZERO),
+    })
+}
+
+// Start of a witness commitment output: OP_RETURN, a 36-byte push, and the
+// 0xaa21a9ed tag (BIP141)
+const WITNESS_COMMITMENT_PREFIX: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+// What the coinbase commits to about the block's witness data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WitnessCommitment {
+    /// Present at coinbase output `vout` and matching the block's witnesses
+    Valid { vout: usize },
+    /// Present but not matching the witnesses; the block would be invalid
+    Invalid { vout: usize },
+    /// Absent although the block holds segwit transactions
+    Missing,
+    /// Absent and not needed, as no transaction carries witness data
+    NotRequired,
+}
+
+impl fmt::Display for WitnessCommitment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WitnessCommitment::Valid { vout } => {
+                write!(f, "present in coinbase output {vout} and valid")
+            }
+            WitnessCommitment::Invalid { vout } => write!(
+                f,
+                "present in coinbase output {vout} but does not match the witnesses"
+            ),
+            WitnessCommitment::Missing => {
+                write!(f, "missing from a block with segwit transactions")
+            }
+            WitnessCommitment::NotRequired => {
+                write!(
+                    f,
+                    "absent, and optional as the block has no segwit transactions"
+                )
+            }
+        }
+    }
+}
+
+// Find the coinbase's witness commitment (the last output carrying the tag
+// counts) and check it against the witness merkle root. Segwit transactions
+// are only committed to through it, so it is mandatory once any is included.
+pub fn verify_witness_commitment(block: &Block) -> WitnessCommitment {
+    let vout = block.txdata.first().and_then(|coinbase| {
+        coinbase.output.iter().rposition(|o| {
+            o.script_pubkey
+                .as_bytes()
+                .starts_with(&WITNESS_COMMITMENT_PREFIX)
+        })
+    });
+    let has_segwit = block
+        .txdata
+        .iter()
+        .skip(1)
+        .any(|tx| tx.input.iter().any(|input| !input.witness.is_empty()));
+    match vout {
+        Some(vout) if block.check_witness_commitment() => WitnessCommitment::Valid { vout },
+        Some(vout) => WitnessCommitment::Invalid { vout },
+        None if has_segwit => WitnessCommitment::Missing,
+        None => WitnessCommitment::NotRequired,
+    }
+}
+
+// Inputs with a sequence below this opt in to replacement (BIP125); the
+// transaction is replaceable if any one of its inputs does
+pub const RBF_SEQUENCE_THRESHOLD: u32 = 0xffff_fffe;
+
+pub fn signals_rbf(sequence: u32) -> bool {
+    sequence < RBF_SEQUENCE_THRESHOLD
+}
+
+// What a single input contributed to the transaction
+#[derive(Debug, Clone)]
+pub struct InputDetail {
+    /// Address of the spent output; empty for coinbase inputs
+    pub address: String,
+    /// Value of the spent output, or the block subsidy for a coinbase input
+    pub amount: Amount,
+    pub coinbase: bool,
+    /// Template of the spent output; `None` for coinbase inputs
+    pub script_type: Option<ScriptType>,
+}
+
+// All inputs of a transaction and their combined value
+#[derive(Debug, Clone)]
+pub struct InputSummary {
+    pub inputs: Vec<InputDetail>,
+    pub total: Amount,
+}
+
+impl InputSummary {
+    // Address of the first input that spent a real prevout
+    pub fn first_address(&self) -> String {
+        self.inputs
+            .iter()
+            .find(|i| !i.coinbase)
+            .map(|i| i.address.clone())
+            .unwrap_or_default()
+    }
+}
+
+// Coinbase inputs carry a `coinbase` field and reference the all-zero txid
+pub fn is_coinbase_input(input: &GetRawTransactionResultVin) -> bool {
+    input.is_coinbase() || input.txid == Some(Txid::all_zeros())
+}
+
+// Standard output templates, with P2SH split by the script it wraps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptType {
+    P2pk,
+    P2pkh,
+    P2sh,
+    P2shP2wpkh,
+    P2shP2wsh,
+    P2wpkh,
+    P2wsh,
+    P2tr,
+    Multisig,
+    NullData,
+    Nonstandard,
+}
+
+impl fmt::Display for ScriptType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ScriptType::P2pk => "P2PK",
+            ScriptType::P2pkh => "P2PKH",
+            ScriptType::P2sh => "P2SH",
+            ScriptType::P2shP2wpkh => "P2SH-P2WPKH",
+            ScriptType::P2shP2wsh => "P2SH-P2WSH",
+            ScriptType::P2wpkh => "P2WPKH",
+            ScriptType::P2wsh => "P2WSH",
+            ScriptType::P2tr => "P2TR",
+            ScriptType::Multisig => "multisig",
+            ScriptType::NullData => "OP_RETURN",
+            ScriptType::Nonstandard => "nonstandard",
+        };
+        f.write_str(name)
+    }
+}
+
+// Classify a scriptPubKey. A P2SH output only reveals what it wraps through
+// its redeem script, taken from the spending scriptSig or, for outputs a
+// wallet owns, from getaddressinfo.
+pub fn script_type(
+    script: &GetRawTransactionResultVoutScriptPubKey,
+    redeem_script: Option<&[u8]>,
+) -> ScriptType {
+    match script.type_ {
+        Some(ScriptPubkeyType::Pubkey) => ScriptType::P2pk,
+        Some(ScriptPubkeyType::PubkeyHash) => ScriptType::P2pkh,
+        Some(ScriptPubkeyType::ScriptHash) => match redeem_script {
+            // Witness v0 programs: OP_0 followed by a 20- or 32-byte push
+            Some([0x00, 0x14, rest @ ..]) if rest.len() == 20 => ScriptType::P2shP2wpkh,
+            Some([0x00, 0x20, rest @ ..]) if rest.len() == 32 => ScriptType::P2shP2wsh,
+            _ => ScriptType::P2sh,
+        },
+        Some(ScriptPubkeyType::Witness_v0_KeyHash) => ScriptType::P2wpkh,
+        Some(ScriptPubkeyType::Witness_v0_ScriptHash) => ScriptType::P2wsh,
+        Some(ScriptPubkeyType::Witness_v1_Taproot) => ScriptType::P2tr,
+        Some(ScriptPubkeyType::MultiSig) => ScriptType::Multisig,
+        Some(ScriptPubkeyType::NullData) => ScriptType::NullData,
+        _ => ScriptType::Nonstandard,
+    }
+}
+
+// The final push of a scriptSig, which for P2SH spends is the redeem script
+pub fn last_push(script_sig: &[u8]) -> Option<Vec<u8>> {
+    match Script::from_bytes(script_sig).instructions().last()? {
+        Ok(Instruction::PushBytes(bytes)) => Some(bytes.as_bytes().to_vec()),
+        _ => None,
+    }
+}
+
+// An output paying back into the sending wallet
+#[derive(Debug, Clone)]
+pub struct ChangeOutput {
+    pub vout: u32,
+    pub address: String,
+    pub amount: Amount,
+}
+
+// Everything returned to the sender. Wallets normally add one change output,
+// but some fundrawtransaction setups split change across several.
+pub fn change_total(changes: &[ChangeOutput]) -> Amount {
+    changes.iter().map(|c| c.amount).sum()
+}
+
+// Whose wallet an output pays, as far as the report is concerned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Owner {
+    Trader,
+    Miner,
+}
+
+// One output of the analysed transaction, however it was decoded
+#[derive(Debug, Clone)]
+pub struct TxOutput {
+    pub vout: u32,
+    /// Empty for outputs without an address, e.g. OP_RETURN
+    pub address: String,
+    pub amount: Amount,
+}
+
+// Outputs as decoderawtransaction reports them
+pub fn decoded_outputs(decoded_tx: &DecodeRawTransactionResult) -> Vec<TxOutput> {
+    decoded_tx
+        .vout
+        .iter()
+        .map(|output| TxOutput {
+            vout: output.n,
+            address: script_address(&output.script_pub_key),
+            amount: output.value,
+        })
+        .collect()
+}
+
+// The outputs the report names: the first one paying the Trader and every one
+// paying the Miner back. Payments to anyone else belong to neither.
+#[derive(Debug, Clone, Default)]
+pub struct OutputSplit {
+    pub trader: Option<TxOutput>,
+    pub change: Vec<ChangeOutput>,
+}
+
+// Sort outputs by the wallet `owner` says each address belongs to. How that is
+// decided differs between a live run, a batch and a fixture; these rules do not.
+pub fn classify_outputs(
+    outputs: &[TxOutput],
+    owner: impl Fn(&str) -> Option<Owner>,
+) -> OutputSplit {
+    let mut split = OutputSplit::default();
+    for output in outputs.iter().filter(|o| !o.address.is_empty()) {
+        match owner(&output.address) {
+            Some(Owner::Trader) if split.trader.is_none() => split.trader = Some(output.clone()),
+            Some(Owner::Miner) => split.change.push(ChangeOutput {
+                vout: output.vout,
+                address: output.address.clone(),
+                amount: output.amount,
+            }),
+            _ => {}
+        }
+    }
+    split
+}
+
+// The report a transaction's inputs, outputs and confirming block make up.
+// The fee is what the inputs hold beyond every output, whoever it pays; the
+// report names the first change address and sums all change. Confirmation
+// details are left for callers that can ask a node.
+pub fn build_report(
+    txid: Txid,
+    inputs: &InputSummary,
+    outputs: &[TxOutput],
+    split: &OutputSplit,
+    block_height: usize,
+    block_hash: BlockHash,
+) -> TransactionReport {
+    let output_total: Amount = outputs.iter().map(|o| o.amount).sum();
+    let (trader_address, trader_amount) = split
+        .trader
+        .as_ref()
+        .map(|t| (t.address.clone(), t.amount))
+        .unwrap_or_default();
+    TransactionReport {
+        txid,
+        input_address: inputs.first_address(),
+        input_amount: inputs.total,
+        trader_address,
+        trader_amount,
+        change_address: split
+            .change
+            .first()
+            .map(|c| c.address.clone())
+            .unwrap_or_default(),
+        change_amount: change_total(&split.change),
+        fee: inputs
+            .total
+            .checked_sub(output_total)
+            .unwrap_or(Amount::ZERO),
+        block_height,
+        block_hash,
+        confirmations: None,
+        confirmation_estimate: ConfirmationEstimate::Unknown,
+    }
+}
+
+// Classify an output, asking each wallet for the redeem script of P2SH
+// outputs it owns so nested segwit can be told apart from plain P2SH
+pub fn output_script_type(
+    wallets: &[&Client],
+    script: &GetRawTransactionResultVoutScriptPubKey,
+) -> ScriptType {
+    if script.type_ != Some(ScriptPubkeyType::ScriptHash) {
+        return script_type(script, None);
+    }
+    let Some(address) = script.address.clone() else {
+        return ScriptType::P2sh;
+    };
+    let address = address.assume_checked();
+    let redeem_script = wallets.iter().find_map(|wallet| {
+        wallet
+            .get_address_info(&address)
+            .ok()
+            .filter(|info| info.is_mine.unwrap_or(false))
+            .and_then(|info| info.hex)
+    });
+    script_type(script, redeem_script.as_deref())
+}
+
+// Recompute the txid from the raw hex with the `bitcoin` crate's own consensus
+// decoding and check it against both the original and the decoded txid
+pub fn verify_decoded_txid(
+    raw_hex: &str,
+    reported: &Txid,
+    decoded_tx: &DecodeRawTransactionResult,
+) -> Result<(), AppError> {
+    let bytes =
+        hex::decode(raw_hex).map_err(|e| AppError::Decode(format!("raw transaction hex: {e}")))?;
+    let tx: bitcoin::Transaction = bitcoin::consensus::deserialize(&bytes)
+        .map_err(|e| AppError::Decode(format!("raw transaction: {e}")))?;
+    let recomputed = tx.compute_txid().to_string();
+
+    for txid in [reported, &decoded_tx.txid] {
+        if txid.to_string() != recomputed {
+            return Err(AppError::TxidMismatch {
+                reported: txid.to_string(),
+                recomputed,
+            });
+        }
+    }
+    Ok(())
+}
+
+// Address a script pays to, across Core versions before and after 22
+pub fn script_address(script: &GetRawTransactionResultVoutScriptPubKey) -> String {
+    script
+        .address
+        .as_ref()
+        .or_else(|| script.addresses.first())
+        .map(|a| a.clone().assume_checked().to_string())
+        .unwrap_or_default()
+}
+
+// First release whose getrawtransaction accepts verbosity 2 (inline prevouts)
+pub const VERBOSE_PREVOUT_VERSION: usize = 250_000;
+
+// Resolve the inputs with a single verbosity-2 getrawtransaction when the node
+// supports it, otherwise by fetching each input's funding transaction
+pub fn resolve_inputs(
+    rpc: &Client,
+    decoded_tx: &DecodeRawTransactionResult,
+    block_hash: &BlockHash,
+    height: u64,
+    node_version: usize,
+) -> Result<InputSummary, AppError> {
+    if node_version >= VERBOSE_PREVOUT_VERSION {
+        if let Some(summary) = sum_inputs_inline(rpc, &decoded_tx.txid, block_hash, height)? {
+            return Ok(summary);
+        }
+    }
+    sum_inputs(rpc, decoded_tx, height)
+}
+
+// Inputs as reported by getrawtransaction verbosity 2
+#[derive(Deserialize)]
+struct InlineTx {
+    vin: Vec<InlineVin>,
+}
+
+#[derive(Deserialize)]
+struct InlineVin {
+    coinbase: Option<String>,
+    #[serde(rename = "scriptSig")]
+    script_sig: Option<GetRawTransactionResultVinScriptSig>,
+    prevout: Option<InlinePrevout>,
+}
+
+#[derive(Deserialize)]
+struct InlinePrevout {
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    value: Amount,
+    #[serde(rename = "scriptPubKey")]
+    script_pub_key: GetRawTransactionResultVoutScriptPubKey,
+}
+
+// Read input amounts and addresses straight from the verbose response.
+// Returns `None` when any prevout is missing (e.g. undo data was pruned).
+fn sum_inputs_inline(
+    rpc: &Client,
+    txid: &Txid,
+    block_hash: &BlockHash,
+    height: u64,
+) -> Result<Option<InputSummary>, AppError> {
+    let tx: InlineTx = rpc.call(
+        "getrawtransaction",
+        &[json!(txid), json!(2), json!(block_hash)],
+    )?;
+    let mut inputs = Vec::with_capacity(tx.vin.len());
+    for input in tx.vin {
+        if input.coinbase.is_some() {
+            inputs.push(InputDetail {
+                address: String::new(),
+                amount: block_subsidy(height),
+                coinbase: true,
+                script_type: None,
+            });
+            continue;
+        }
+        let Some(prevout) = input.prevout else {
+            return Ok(None);
+        };
+        let redeem_script = input.script_sig.as_ref().and_then(|s| last_push(&s.hex));
+        inputs.push(InputDetail {
+            address: script_address(&prevout.script_pub_key),
+            amount: prevout.value,
+            coinbase: false,
+            script_type: Some(script_type(
+                &prevout.script_pub_key,
+                redeem_script.as_deref(),
+            )),
+        });
+    }
+
+    let total = inputs.iter().map(|i| i.amount).sum();
+    Ok(Some(InputSummary { inputs, total }))
+}
+
+// Resolve every input's prevout and total them. Coinbase inputs have no
+// prevout, so they are attributed the subsidy of the block at `height`.
+pub fn sum_inputs(
+    rpc: &Client,
+    decoded_tx: &DecodeRawTransactionResult,
+    height: u64,
+) -> Result<InputSummary, AppError> {
+    let mut inputs = Vec::with_capacity(decoded_tx.vin.len());
+    for input in &decoded_tx.vin {
+        if is_coinbase_input(input) {
+            inputs.push(InputDetail {
+                address: String::new(),
+                amount: block_subsidy(height),
+                coinbase: true,
+                script_type: None,
+            });
+            continue;
+        }
+
+        let (Some(prev_txid), Some(prev_vout)) = (input.txid, input.vout) else {
+            return Err(bitcoincore_rpc::Error::UnexpectedStructure.into());
+        };
+        let prev_tx = rpc.get_raw_transaction(&prev_txid, None)?;
+        let prev_decoded = rpc.decode_raw_transaction(&prev_tx, None)?;
+        let prev_output = prev_decoded
+            .vout
+            .get(prev_vout as usize)
+            .ok_or(bitcoincore_rpc::Error::UnexpectedStructure)?;
+        let redeem_script = input.script_sig.as_ref().and_then(|s| last_push(&s.hex));
+        inputs.push(InputDetail {
+            address: script_address(&prev_output.script_pub_key),
+            amount: prev_output.value,
+            coinbase: false,
+            script_type: Some(script_type(
+                &prev_output.script_pub_key,
+                redeem_script.as_deref(),
+            )),
+        });
+    }
+
+    let total = inputs.iter().map(|i| i.amount).sum();
+    Ok(InputSummary { inputs, total })
+}
+
+// Deepest ancestry walk accepted by --trace-prevouts
+pub const MAX_TRACE_DEPTH: u32 = 8;
+// Upper bound on transactions fetched by one walk, however wide it fans out
+const MAX_TRACE_NODES: usize = 256;
+
+// One funding transaction in an ancestry tree
+#[derive(Debug, Clone)]
+pub struct AncestryNode {
+    pub txid: Txid,
+    /// Output of this transaction spent by the child; `None` for the root
+    pub vout: Option<u32>,
+    /// Value of the spent output, or of all outputs for the root
+    pub amount: Amount,
+    pub coinbase: bool,
+    pub parents: Vec<AncestryNode>,
+    /// Parents were not fetched because the node budget ran out
+    pub truncated: bool,
+}
+
+// Walk back `depth` levels of inputs from `txid`, building the tree of
+// transactions that funded it
+pub fn trace_ancestry(rpc: &Client, txid: &Txid, depth: u32) -> Result<AncestryNode, AppError> {
+    let mut budget = MAX_TRACE_NODES;
+    trace_node(rpc, txid, None, depth.min(MAX_TRACE_DEPTH), &mut budget)
+}
+
+fn trace_node(
+    rpc: &Client,
+    txid: &Txid,
+    vout: Option<u32>,
+    depth: u32,
+    budget: &mut usize,
+) -> Result<AncestryNode, AppError> {
+    *budget = budget.saturating_sub(1);
+    let tx = rpc.get_raw_transaction(txid, None)?;
+    let amount = match vout {
+        Some(vout) => {
+            tx.output
+                .get(vout as usize)
+                .ok_or(bitcoincore_rpc::Error::UnexpectedStructure)?
+                .value
+        }
+        None => tx.output.iter().map(|o| o.value).sum(),
+    };
+
+    let mut node = AncestryNode {
+        txid: *txid,
+        vout,
+        amount,
+        coinbase: tx.is_coinbase(),
+        parents: Vec::new(),
+        truncated: false,
+    };
+    if depth == 0 || node.coinbase {
+        return Ok(node);
+    }
+    for input in &tx.input {
+        if *budget == 0 {
+            node.truncated = true;
+            break;
+        }
+        let prev = input.previous_output;
+        node.parents.push(trace_node(
+            rpc,
+            &prev.txid,
+            Some(prev.vout),
+            depth - 1,
+            budget,
+        )?);
+    }
+    Ok(node)
+}
+
+// Print the tree with each level of ancestry indented one step further
+pub fn print_ancestry(node: &AncestryNode, indent: usize) {
+    let outpoint = match node.vout {
+        Some(vout) => format!("{}:{vout}", node.txid),
+        None => node.txid.to_string(),
+    };
+    let coinbase = if node.coinbase { " (coinbase)" } else { "" };
+    info!(
+        "{:indent$}{outpoint} {} BTC{coinbase}",
+        "",
+        amount::to_btc_string(node.amount),
+        indent = indent * 2
+    );
+    for parent in &node.parents {
+        print_ancestry(parent, indent + 1);
+    }
+    if node.truncated {
+        info!(
+            "{:indent$}... (trace limit reached)",
+            "",
+            indent = (indent + 1) * 2
+        );
+    }
+}
+
+// getblock verbosity 3 (Core 24+) carries every input's prevout inline
+pub const BLOCK_PREVOUT_VERSION: usize = 240_000;
+
+// Fee paid by one transaction in a block
+#[derive(Debug, Clone)]
+pub struct TxFee {
+    pub txid: Txid,
+    pub vsize: u32,
+    /// `None` for the coinbase, which collects fees rather than paying them
+    pub fee: Option<Amount>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockFees {
+    pub hash: BlockHash,
+    pub height: u64,
+    pub txs: Vec<TxFee>,
+    pub total: Amount,
+}
+
+#[derive(Deserialize)]
+struct InlineBlock {
+    height: u64,
+    tx: Vec<InlineBlockTx>,
+}
+
+#[derive(Deserialize)]
+struct InlineBlockTx {
+    txid: Txid,
+    vsize: u32,
+    vin: Vec<InlineVin>,
+    vout: Vec<InlineVout>,
+}
+
+#[derive(Deserialize)]
+struct InlineVout {
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    value: Amount,
+}
+
+// Fee of every transaction in a block. On Core 24+ a single getblock call
+// supplies all prevouts; older nodes, or blocks whose undo data was pruned,
+// fall back to resolving each transaction's inputs separately.
+pub fn analyze_block(
+    rpc: &Client,
+    block_hash: &BlockHash,
+    node_version: usize,
+) -> Result<BlockFees, AppError> {
+    if node_version >= BLOCK_PREVOUT_VERSION {
+        if let Some(fees) = analyze_block_inline(rpc, block_hash)? {
+            return Ok(fees);
+        }
+    }
+
+    let info = rpc.get_block_info(block_hash)?;
+    let height = info.height as u64;
+    let mut txs = Vec::with_capacity(info.tx.len());
+    for txid in &info.tx {
+        let raw = rpc.get_raw_transaction_hex(txid, Some(block_hash))?;
+        let decoded = rpc.decode_raw_transaction(raw.as_str(), None)?;
+        let fee = if decoded.vin.iter().any(is_coinbase_input) {
+            None
+        } else {
+            let inputs = resolve_inputs(rpc, &decoded, block_hash, height, node_version)?;
+            let outputs: Amount = decoded.vout.iter().map(|o| o.value).sum();
+            Some(inputs.total.checked_sub(outputs).unwrap_or(Amount::ZERO))
+        };
+        txs.push(TxFee {
+            txid: *txid,
+            vsize: decoded.vsize,
+            fee,
+        });
+    }
+    Ok(block_fees(*block_hash, height, txs))
+}
+
+fn analyze_block_inline(
+    rpc: &Client,
+    block_hash: &BlockHash,
+) -> Result<Option<BlockFees>, AppError> {
+    let block: InlineBlock = rpc.call("getblock", &[json!(block_hash), json!(3)])?;
+    let mut txs = Vec::with_capacity(block.tx.len());
+    for tx in block.tx {
+        let fee = if tx.vin.iter().any(|i| i.coinbase.is_some()) {
+            None
+        } else {
+            let mut inputs = Amount::ZERO;
+            for input in &tx.vin {
+                let Some(prevout) = &input.prevout else {
+                    return Ok(None);
+                };
+                inputs += prevout.value;
+            }
+            let outputs: Amount = tx.vout.iter().map(|o| o.value).sum();
+            Some(inputs.checked_sub(outputs).unwrap_or(Amount::ZERO))
+        };
+        txs.push(TxFee {
+            txid: tx.txid,
+            vsize: tx.vsize,
+            fee,
+        });
+    }
+    Ok(Some(block_fees(*block_hash, block.height, txs)))
+}
+
+fn block_fees(hash: BlockHash, height: u64, txs: Vec<TxFee>) -> BlockFees {
+    let total = txs.iter().filter_map(|t| t.fee).sum();
+    BlockFees {
+        hash,
+        height,
+        txs,
+        total,
+    }
+}
+
+// Outputs sharing one value
+#[derive(Debug, Clone)]
+pub struct EqualValueGroup {
+    pub amount: Amount,
+    pub vouts: Vec<u32>,
+}
+
+// Group outputs that pay an identical value. Several equal outputs is the
+// classic CoinJoin heuristic: an observer cannot tell which input funded which
+// of them. Ordinary payments rarely produce such groups by chance.
+pub fn detect_equal_value_outputs(decoded_tx: &DecodeRawTransactionResult) -> Vec<EqualValueGroup> {
+    let mut by_value: BTreeMap<Amount, Vec<u32>> = BTreeMap::new();
+    for output in &decoded_tx.vout {
+        by_value.entry(output.value).or_default().push(output.n);
+    }
+    by_value
+        .into_iter()
+        .filter(|(_, vouts)| vouts.len() > 1)
+        .map(|(amount, vouts)| EqualValueGroup { amount, vouts })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoincore_rpc::bitcoin::hashes::Hash;
+    use bitcoincore_rpc::bitcoin::{Address, Network, ScriptBuf, Wtxid};
+    use bitcoincore_rpc::json::GetRawTransactionResultVout;
+
+    // A distinct regtest address per seed
+    fn address(seed: u8) -> Address {
+        Address::p2wsh(&ScriptBuf::from_bytes(vec![seed]), Network::Regtest)
+    }
+
+    fn decoded(outputs: &[(&Address, u64)]) -> DecodeRawTransactionResult {
+        DecodeRawTransactionResult {
+            txid: Txid::all_zeros(),
+            hash: Wtxid::all_zeros(),
+            size: 0,
+            vsize: 0,
+            weight: 0,
+            version: 2,
+            locktime: 0,
+            vin: Vec::new(),
+            vout: outputs
+                .iter()
+                .enumerate()
+                .map(|(n, (address, sats))| GetRawTransactionResultVout {
+                    value: Amount::from_sat(*sats),
+                    n: n as u32,
+                    script_pub_key: GetRawTransactionResultVoutScriptPubKey {
+                        asm: String::new(),
+                        hex: address.script_pubkey().to_bytes(),
+                        req_sigs: None,
+                        type_: None,
+                        addresses: Vec::new(),
+                        address: Some(address.as_unchecked().clone()),
+                    },
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn classify_sums_two_change_outputs() {
+        let (trader, change_a, change_b, other) = (address(1), address(2), address(3), address(4));
+        let tx = decoded(&[
+            (&change_a, 1_000_000_000),
+            (&trader, 2_000_000_000),
+            (&other, 300_000_000),
+            (&change_b, 699_990_000),
+        ]);
+        let miner = [change_a.to_string(), change_b.to_string()];
+        let outputs = decoded_outputs(&tx);
+        let split = classify_outputs(&outputs, |a| {
+            if a == trader.to_string() {
+                Some(Owner::Trader)
+            } else if miner.iter().any(|m| m == a) {
+                Some(Owner::Miner)
+            } else {
+                None
+            }
+        });
+
+        let paid = split.trader.as_ref().unwrap();
+        assert_eq!(
+            (paid.vout, paid.amount),
+            (1, Amount::from_sat(2_000_000_000))
+        );
+        let vouts: Vec<u32> = split.change.iter().map(|c| c.vout).collect();
+        assert_eq!(vouts, [0, 3]);
+        assert_eq!(change_total(&split.change), Amount::from_sat(1_699_990_000));
+
+        let inputs = InputSummary {
+            inputs: Vec::new(),
+            total: Amount::from_sat(4_000_000_000),
+        };
+        let report = build_report(
+            tx.txid,
+            &inputs,
+            &outputs,
+            &split,
+            102,
+            BlockHash::all_zeros(),
+        );
+        assert_eq!(report.change_address, change_a.to_string());
+        assert_eq!(report.change_amount, Amount::from_sat(1_699_990_000));
+        // The payment to the third party is not fee
+        assert_eq!(report.fee, Amount::from_sat(10_000));
+    }
+
+    #[test]
+    fn only_sequences_below_the_threshold_signal_rbf() {
+        assert!(signals_rbf(0xffff_fffd));
+        assert!(signals_rbf(0));
+        assert!(!signals_rbf(0xffff_fffe));
+        assert!(!signals_rbf(0xffff_ffff));
+    }
+}
@@ -0,0 +1,113 @@
+// Concurrent analysis of many transactions, one report per txid
+use crate::analysis;
+use crate::config::Config;
+use crate::error::AppError;
+use crate::fees;
+use crate::report::TransactionReport;
+use crate::wallet;
+use bitcoincore_rpc::bitcoin::{Network, Txid};
+use bitcoincore_rpc::{Client, RpcApi};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+// Worker threads used when --workers is not given
+pub const DEFAULT_WORKERS: usize = 4;
+
+// Reports for the txids that could be analysed, and the error for each that could not
+#[derive(Debug, Default)]
+pub struct BatchOutcome {
+    pub reports: Vec<TransactionReport>,
+    pub errors: Vec<(Txid, AppError)>,
+}
+
+// Build the report for one confirmed transaction. The Trader output is the
+// one the Trader wallet owns and the change every one the Miner wallet owns.
+pub fn analyze_tx(
+    miner: &Client,
+    trader: &Client,
+    txid: &Txid,
+    node_version: usize,
+    network: Network,
+) -> Result<TransactionReport, AppError> {
+    let info = miner.get_raw_transaction_info(txid, None)?;
+    let block_hash = info
+        .blockhash
+        .ok_or_else(|| AppError::Unsupported(format!("{txid} is not confirmed yet")))?;
+    let block_height = miner.get_block_info(&block_hash)?.height;
+
+    let raw_tx = miner.get_raw_transaction_hex(txid, Some(&block_hash))?;
+    let decoded = miner.decode_raw_transaction(raw_tx.as_str(), None)?;
+    let inputs = analysis::resolve_inputs(
+        miner,
+        &decoded,
+        &block_hash,
+        block_height as u64,
+        node_version,
+    )?;
+
+    let outputs = analysis::decoded_outputs(&decoded);
+    let split = analysis::classify_outputs(&outputs, |address| {
+        if wallet::owns_address(trader, address) {
+            Some(analysis::Owner::Trader)
+        } else if wallet::owns_address(miner, address) {
+            Some(analysis::Owner::Miner)
+        } else {
+            None
+        }
+    });
+    let mut report =
+        analysis::build_report(*txid, &inputs, &outputs, &split, block_height, block_hash);
+    let fee_rate = fees::fee_rate_sat_vb(report.fee, decoded.vsize);
+    report.confirmation_estimate = fees::estimate_confirmation(miner, network, fee_rate)?;
+    Ok(report)
+}
+
+// Analyse `txids` on `config.workers` threads, each with its own pair of wallet
+// clients. Reports come back in the order the txids were given.
+pub fn analyze_many(
+    config: &Config,
+    txids: &[Txid],
+    node_version: usize,
+    network: Network,
+) -> BatchOutcome {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(txids.len()));
+    let workers = config.workers.clamp(1, txids.len().max(1));
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                let clients = crate::wallet_client(config, &config.miner_wallet)
+                    .and_then(|m| Ok((m, crate::wallet_client(config, &config.trader_wallet)?)));
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(txid) = txids.get(index) else {
+                        break;
+                    };
+                    let result = match &clients {
+                        Ok((miner, trader)) => {
+                            analyze_tx(miner, trader, txid, node_version, network)
+                        }
+                        Err(e) => Err(AppError::Unsupported(format!("could not connect: {e}"))),
+                    };
+                    results
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .push((index, result));
+                }
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap_or_else(|e| e.into_inner());
+    results.sort_by_key(|(index, _)| *index);
+    let mut outcome = BatchOutcome::default();
+    for (index, result) in results {
+        match result {
+            Ok(report) => outcome.reports.push(report),
+            Err(e) => outcome.errors.push((txids[index], e)),
+        }
+    }
+    outcome
+}
@@ -0,0 +1,156 @@
+// BIP21 payment URIs: bitcoin:ADDRESS?amount=X&label=Y&message=Z
+use crate::amount::{self, Unit};
+use crate::error::AppError;
+use bitcoincore_rpc::bitcoin::address::NetworkUnchecked;
+use bitcoincore_rpc::bitcoin::{Address, Amount};
+use std::str::FromStr;
+
+// The payment a URI asks for; the network is checked once the node's chain is known
+#[derive(Debug, Clone)]
+pub struct PaymentUri {
+    pub address: Address<NetworkUnchecked>,
+    /// Requested amount in BTC; absent when the payer chooses
+    pub amount: Option<Amount>,
+    pub label: Option<String>,
+    pub message: Option<String>,
+}
+
+impl FromStr for PaymentUri {
+    type Err = AppError;
+
+    fn from_str(uri: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            |reason: String| AppError::Usage(format!("invalid BIP21 URI '{uri}': {reason}"));
+
+        // The scheme is case-insensitive, e.g. from an uppercased QR code
+        let rest = uri
+            .get(..8)
+            .filter(|scheme| scheme.eq_ignore_ascii_case("bitcoin:"))
+            .map(|_| &uri[8..])
+            .ok_or_else(|| invalid("scheme must be bitcoin:".to_string()))?;
+        let (address, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let address = Address::from_str(address)
+            .map_err(|e| invalid(format!("bad address '{address}': {e}")))?;
+
+        let mut payment = PaymentUri {
+            address,
+            amount: None,
+            label: None,
+            message: None,
+        };
+        for param in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = param
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("parameter '{param}' has no value")))?;
+            let value = percent_decode(value).map_err(invalid)?;
+            let duplicate = match key {
+                "amount" => {
+                    let value = amount::parse_amount(&value, Unit::Btc)
+                        .map_err(|e| invalid(format!("bad amount '{value}': {e}")))?;
+                    payment.amount.replace(value).is_some()
+                }
+                "label" => payment.label.replace(value).is_some(),
+                "message" => payment.message.replace(value).is_some(),
+                // Required parameters we do not understand make the URI unusable
+                key if key.starts_with("req-") => {
+                    return Err(invalid(format!("unsupported required parameter '{key}'")));
+                }
+                _ => false,
+            };
+            if duplicate {
+                return Err(invalid(format!("parameter '{key}' given twice")));
+            }
+        }
+        Ok(payment)
+    }
+}
+
+// Undo %XX escapes, which must decode to UTF-8
+fn percent_decode(value: &str) -> Result<String, String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut chars = value.bytes();
+    while let Some(byte) = chars.next() {
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+        let escape: Vec<u8> = chars.by_ref().take(2).collect();
+        let decoded = std::str::from_utf8(&escape)
+            .ok()
+            .filter(|hex| hex.len() == 2 && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| format!("bad percent escape in '{value}'"))?;
+        bytes.push(decoded);
+    }
+    String::from_utf8(bytes).map_err(|_| format!("'{value}' is not UTF-8 once decoded"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+
+    fn parse(uri: &str) -> Result<PaymentUri, AppError> {
+        uri.parse()
+    }
+
+    #[test]
+    fn scheme_is_case_insensitive() {
+        let payment = parse(&format!("BITCOIN:{ADDRESS}")).unwrap();
+        assert_eq!(payment.address.assume_checked().to_string(), ADDRESS);
+        assert!(parse(&format!("BitCoin:{ADDRESS}")).is_ok());
+        assert!(parse(&format!("litecoin:{ADDRESS}")).is_err());
+        assert!(parse(ADDRESS).is_err());
+    }
+
+    #[test]
+    fn reads_amount_label_and_message() {
+        let payment = parse(&format!(
+            "bitcoin:{ADDRESS}?amount=20.5&label=Trader&message=Payment"
+        ))
+        .unwrap();
+        assert_eq!(payment.amount, Some(Amount::from_sat(2_050_000_000)));
+        assert_eq!(payment.label.as_deref(), Some("Trader"));
+        assert_eq!(payment.message.as_deref(), Some("Payment"));
+
+        let bare = parse(&format!("bitcoin:{ADDRESS}")).unwrap();
+        assert_eq!(bare.amount, None);
+        assert_eq!(bare.label, None);
+        assert!(parse(&format!("bitcoin:{ADDRESS}?amount=abc")).is_err());
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        let payment = parse(&format!(
+            "bitcoin:{ADDRESS}?label=Caf%C3%A9%20Bar&message=50%25"
+        ))
+        .unwrap();
+        assert_eq!(payment.label.as_deref(), Some("Café Bar"));
+        assert_eq!(payment.message.as_deref(), Some("50%"));
+    }
+
+    #[test]
+    fn rejects_invalid_and_truncated_escapes() {
+        assert!(percent_decode("%zz").is_err());
+        assert!(percent_decode("%+1").is_err());
+        assert!(percent_decode("abc%4").is_err());
+        assert!(percent_decode("abc%").is_err());
+        // A valid escape that is not UTF-8 on its own
+        assert!(percent_decode("%C3").is_err());
+        assert!(parse(&format!("bitcoin:{ADDRESS}?label=%G0")).is_err());
+    }
+
+    #[test]
+    fn rejects_duplicate_parameters() {
+        assert!(parse(&format!("bitcoin:{ADDRESS}?amount=1&amount=2")).is_err());
+        assert!(parse(&format!("bitcoin:{ADDRESS}?label=a&label=b")).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_required_parameters() {
+        assert!(parse(&format!("bitcoin:{ADDRESS}?req-somethingnew=1")).is_err());
+        // Unknown optional parameters are ignored
+        assert!(parse(&format!("bitcoin:{ADDRESS}?somethingnew=1")).is_ok());
+    }
+}
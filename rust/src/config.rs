@@ -0,0 +1,682 @@
+// Command-line options for the run
+// Running without arguments performs the default Miner -> Trader walkthrough.
+use crate::amount::{self, DisplayFormat, Unit};
+use crate::analysis::{MAX_TRACE_DEPTH, REGTEST_HALVING_INTERVAL};
+use crate::batch;
+use crate::bip21::PaymentUri;
+use crate::error::AppError;
+use crate::report::ReportFormat;
+use crate::rpc;
+use crate::watch;
+use bitcoincore_rpc::bitcoin::address::NetworkUnchecked;
+use bitcoincore_rpc::bitcoin::{Address, Amount, OutPoint, Txid};
+use bitcoincore_rpc::json::AddressType;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+// Upper bound on blocks mined while waiting for a spendable balance
+pub const DEFAULT_MAX_BLOCKS: u64 = 500;
+
+// Outputs that pay the fee, as given to --subtract-fee-from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubtractFeeFrom {
+    All,
+    Indices(Vec<usize>),
+}
+
+impl SubtractFeeFrom {
+    // Recipient indices the fee is deducted from, out of `count` recipients
+    pub fn indices(&self, count: usize) -> Vec<usize> {
+        match self {
+            SubtractFeeFrom::All => (0..count).collect(),
+            SubtractFeeFrom::Indices(indices) => indices.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Wallet that mines and pays, created or loaded by name
+    pub miner_wallet: String,
+    /// Wallet that receives the payment, created or loaded by name
+    pub trader_wallet: String,
+    /// Times to try the whole run when it fails on a retryable error
+    pub attempts: u32,
+    /// Sweep with the `sendall` RPC instead of paying a fixed 20 BTC
+    pub sendall: bool,
+    /// Inputs to sweep in `--sendall` mode; empty sweeps the whole wallet
+    pub sendall_inputs: Vec<OutPoint>,
+    /// Recipients of the sweep; empty pays the Trader's address
+    pub sendall_recipients: Vec<String>,
+    /// Give up mining for a spendable balance after this many blocks
+    pub max_blocks: u64,
+    /// Append a timestamped report block to out.txt instead of replacing it
+    pub output_append: bool,
+    /// Pay the Trader with the `send` RPC instead of `sendtoaddress`
+    pub send_rpc: bool,
+    /// Node version to shape `send` params for; detected when unset
+    pub core_version: Option<usize>,
+    /// Print nothing but errors; out.txt is still written
+    pub quiet: bool,
+    /// Narrate each step with the RPC it calls and why
+    pub explain: bool,
+    /// Levels of input ancestry to trace; 0 disables tracing
+    pub trace_prevouts: u32,
+    /// Mine exactly to the coinbase maturity boundary and assert the transition
+    pub check_maturity: bool,
+    /// Print how much of the fee each input's size accounts for
+    pub include_fee_breakdown: bool,
+    /// Sweep every Miner UTXO into one new address instead of paying the Trader
+    pub consolidate: bool,
+    /// Preview the inputs, change and fee with fundrawtransaction before sending
+    pub simulate: bool,
+    /// Print the send preview and stop without broadcasting anything
+    pub dry_run: bool,
+    /// List each input's sequence number and whether it signals RBF
+    pub include_inputs: bool,
+    /// Report format; txt is the ten-line out.txt the autograder reads
+    pub format: ReportFormat,
+    /// Append the confirmation count to the report as an eleventh field
+    pub extended_report: bool,
+    /// How amounts appear in the printed summary; never affects report files
+    pub display_format: DisplayFormat,
+    /// Skip the confirmation prompt before sending on a non-regtest network
+    pub yes: bool,
+    /// Also write the raw transaction hex to out.hex
+    pub save_raw_tx: bool,
+    /// Type of the generated Miner and Trader addresses; node default when unset
+    pub address_type: Option<AddressType>,
+    /// Rescan the Miner wallet from this height before mining
+    pub rescan_from: Option<usize>,
+    /// Abort a rescan in progress on either wallet and exit
+    pub abort_rescan: bool,
+    /// Pay these recipients in one sendmany instead of 20 BTC to the Trader
+    pub recipients: Vec<(String, Amount)>,
+    /// Fund the payment at exactly this many sat/vB instead of estimating a fee
+    pub deterministic_fee: Option<u64>,
+    /// Blocks between subsidy halvings, as given to -subsidyhalvinginterval
+    pub halving_interval: u64,
+    /// Monitor this wallet for new transactions instead of running the flow
+    pub watch: Option<String>,
+    /// Seconds between --watch polls
+    pub watch_interval: u64,
+    /// Save the mempool to this file just before the transfer is confirmed
+    pub dump_mempool: Option<PathBuf>,
+    /// Re-broadcast a --dump-mempool file before the run sends anything
+    pub load_mempool: Option<PathBuf>,
+    /// Build a fee history before sending so estimatesmartfee has data
+    pub warmup_feeest: bool,
+    /// Let the Trader output check count outputs that are still unconfirmed
+    pub include_mempool: bool,
+    /// Pay the address, amount and label of a BIP21 URI instead of the Trader
+    pub pay: Option<PaymentUri>,
+    /// Deduct the fee from these recipients instead of adding it on top
+    pub subtract_fee_from: Option<SubtractFeeFrom>,
+    /// Write the effective settings to this env file and exit
+    pub export_env: Option<PathBuf>,
+    /// Write the RPC password to the env file instead of redacting it
+    pub include_secrets: bool,
+    /// Re-read out.txt after writing and check its block hash against the node
+    pub verify_report: bool,
+    /// Send coinbase rewards here instead of a new Miner wallet address
+    pub mine_to: Option<Address<NetworkUnchecked>>,
+    /// Fill the mempool with about this many kvB of low-fee fillers before
+    /// sending; regtest only, and see mempool.rs for the node settings it needs
+    pub demo_mempool_pressure: Option<usize>,
+    /// Fail the run if it makes more than this many RPC round-trips;
+    /// `--rpc-budget baseline` uses the budget of a default run
+    pub rpc_budget: Option<usize>,
+    /// Fail if the transaction pays change back to the Miner
+    pub no_change_expected: bool,
+    /// Report the fee of every transaction in the confirming block
+    pub analyze_block: bool,
+    /// Compare the new report field by field against this earlier out.txt
+    pub diff: Option<PathBuf>,
+    /// Include the txid, which differs on every run, in --diff
+    pub strict: bool,
+    /// Blocks mined after the send; the report reflects this many confirmations
+    pub confirm_depth: u64,
+    /// Check each wallet's balance against the sum of its UTXOs after the flow
+    pub reconcile: bool,
+    /// Permit paying an address that was already paid earlier in the run
+    pub allow_reuse: bool,
+    /// Write the Miner's per-block ledger from this height to ledger.csv and exit
+    pub since_height: Option<u64>,
+    /// Report privacy heuristics, such as equal-value outputs, for the transaction
+    pub privacy_analysis: bool,
+    /// Fail unless the Trader's confirmed balance ends up at exactly this amount
+    pub balance_assert: Option<Amount>,
+    /// Capture the analysed transaction and its inputs into this directory
+    pub fixture: Option<PathBuf>,
+    /// Replay the analysis from a captured fixture, without a node, and exit
+    pub from_fixture: Option<PathBuf>,
+    /// Sign the spend described in this JSON file without wallet RPCs, write
+    /// it to out.hex and exit
+    #[cfg(feature = "offline-sign")]
+    pub offline_sign: Option<PathBuf>,
+    /// Also broadcast the --offline-sign transaction through the node
+    #[cfg(feature = "offline-sign")]
+    pub offline_broadcast: bool,
+    /// Analyse these confirmed transactions, one report each, and exit
+    pub analyze_txids: Vec<Txid>,
+    /// Threads used to analyse --analyze transactions
+    pub workers: usize,
+    /// Node RPC endpoint; BITCOIN_RPC_URL overrides the regtest default
+    pub rpc_url: String,
+    /// BITCOIN_RPC_USER overrides the default
+    pub rpc_user: String,
+    /// BITCOIN_RPC_PASSWORD overrides the default
+    pub rpc_password: String,
+    /// The options this run was given, CAPSTONE_ARGS first, minus the
+    /// --export-env ones; an exported env file replays them
+    pub args: Vec<String>,
+}
+
+// Environment variables a file written by --export-env sets
+const RPC_URL_VAR: &str = "BITCOIN_RPC_URL";
+const RPC_USER_VAR: &str = "BITCOIN_RPC_USER";
+const RPC_PASSWORD_VAR: &str = "BITCOIN_RPC_PASSWORD";
+// Options read before the command line's own, one per line
+const ARGS_VAR: &str = "CAPSTONE_ARGS";
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            miner_wallet: "Miner".to_string(),
+            trader_wallet: "Trader".to_string(),
+            sendall: false,
+            sendall_inputs: Vec::new(),
+            sendall_recipients: Vec::new(),
+            max_blocks: DEFAULT_MAX_BLOCKS,
+            output_append: false,
+            send_rpc: false,
+            core_version: None,
+            quiet: false,
+            explain: false,
+            trace_prevouts: 0,
+            check_maturity: false,
+            include_fee_breakdown: false,
+            include_inputs: false,
+            consolidate: false,
+            simulate: false,
+            dry_run: false,
+            format: ReportFormat::Txt,
+            extended_report: false,
+            display_format: DisplayFormat::Plain,
+            yes: false,
+            save_raw_tx: false,
+            address_type: None,
+            rescan_from: None,
+            abort_rescan: false,
+            recipients: Vec::new(),
+            deterministic_fee: None,
+            halving_interval: REGTEST_HALVING_INTERVAL,
+            watch: None,
+            watch_interval: watch::DEFAULT_INTERVAL_SECS,
+            dump_mempool: None,
+            load_mempool: None,
+            warmup_feeest: false,
+            include_mempool: true,
+            pay: None,
+            subtract_fee_from: None,
+            export_env: None,
+            include_secrets: false,
+            verify_report: false,
+            mine_to: None,
+            demo_mempool_pressure: None,
+            rpc_budget: None,
+            no_change_expected: false,
+            analyze_block: false,
+            diff: None,
+            strict: false,
+            confirm_depth: 1,
+            reconcile: false,
+            allow_reuse: false,
+            since_height: None,
+            privacy_analysis: false,
+            balance_assert: None,
+            fixture: None,
+            from_fixture: None,
+            #[cfg(feature = "offline-sign")]
+            offline_sign: None,
+            #[cfg(feature = "offline-sign")]
+            offline_broadcast: false,
+            analyze_txids: Vec::new(),
+            workers: batch::DEFAULT_WORKERS,
+            attempts: 1,
+            rpc_url: crate::NODE_URL.to_string(),
+            rpc_user: crate::NODE_USER.to_string(),
+            rpc_password: crate::NODE_PASS.to_string(),
+            args: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_args<I>(args: I) -> Result<Self, AppError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut config = Config::default();
+        let env = |name| std::env::var(name).ok().filter(|v: &String| !v.is_empty());
+        if let Some(url) = env(RPC_URL_VAR) {
+            config.rpc_url = url;
+        }
+        if let Some(user) = env(RPC_USER_VAR) {
+            config.rpc_user = user;
+        }
+        if let Some(password) = env(RPC_PASSWORD_VAR) {
+            config.rpc_password = password;
+        }
+        // Options from an exported env file come first, so the command line
+        // can still override them
+        let given: Vec<String> = env(ARGS_VAR)
+            .map(|v| v.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+        let given: Vec<String> = given.into_iter().chain(args).collect();
+        config.args = without_export_options(&given);
+
+        let mut args = given.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--miner-wallet" => config.miner_wallet = next_value(&mut args, &arg)?,
+                "--trader-wallet" => config.trader_wallet = next_value(&mut args, &arg)?,
+                "--sendall" => config.sendall = true,
+                "--sendall-input" => {
+                    let value = next_value(&mut args, &arg)?;
+                    let outpoint = OutPoint::from_str(&value).map_err(|e| {
+                        AppError::Usage(format!(
+                            "invalid outpoint '{value}' (expected txid:vout): {e}"
+                        ))
+                    })?;
+                    config.sendall_inputs.push(outpoint);
+                }
+                "--sendall-to" => config.sendall_recipients.push(next_value(&mut args, &arg)?),
+                "--max-blocks" => {
+                    config.max_blocks = parse_value(&next_value(&mut args, &arg)?, &arg)?;
+                    if config.max_blocks == 0 {
+                        return Err(AppError::Usage(
+                            "--max-blocks must be at least 1".to_string(),
+                        ));
+                    }
+                }
+                "--output-append" => config.output_append = true,
+                "--send-rpc" => config.send_rpc = true,
+                "--core-version" => {
+                    let value = next_value(&mut args, &arg)?;
+                    config.core_version = Some(parse_core_version(&value)?);
+                }
+                "--quiet" | "-q" => config.quiet = true,
+                "--trace-prevouts" => {
+                    config.trace_prevouts = parse_value(&next_value(&mut args, &arg)?, &arg)?;
+                    if config.trace_prevouts > MAX_TRACE_DEPTH {
+                        return Err(AppError::Usage(format!(
+                            "--trace-prevouts is limited to {MAX_TRACE_DEPTH} levels"
+                        )));
+                    }
+                }
+                "--check-maturity" => config.check_maturity = true,
+                "--include-fee-breakdown" => config.include_fee_breakdown = true,
+                "--include-inputs" => config.include_inputs = true,
+                "--consolidate" => config.consolidate = true,
+                "--simulate" => config.simulate = true,
+                "--dry-run" => config.dry_run = true,
+                "--format" => config.format = parse_value(&next_value(&mut args, &arg)?, &arg)?,
+                "--extended-report" => config.extended_report = true,
+                "--display-format" => {
+                    config.display_format = parse_value(&next_value(&mut args, &arg)?, &arg)?
+                }
+                "--yes" | "-y" => config.yes = true,
+                "--save-raw-tx" => config.save_raw_tx = true,
+                "--address-type" => {
+                    let value = next_value(&mut args, &arg)?;
+                    config.address_type = Some(parse_address_type(&value)?);
+                }
+                "--rescan" => {
+                    config.rescan_from = Some(parse_value(&next_value(&mut args, &arg)?, &arg)?)
+                }
+                "--abort-rescan" => config.abort_rescan = true,
+                "--recipient" => {
+                    let value = next_value(&mut args, &arg)?;
+                    config.recipients.push(parse_recipient(&value)?);
+                }
+                "--deterministic-fee" => {
+                    let rate: u64 = parse_value(&next_value(&mut args, &arg)?, &arg)?;
+                    if rate == 0 {
+                        return Err(AppError::Usage(
+                            "--deterministic-fee must be at least 1 sat/vB".to_string(),
+                        ));
+                    }
+                    config.deterministic_fee = Some(rate);
+                }
+                "--halving-interval" => {
+                    config.halving_interval = parse_value(&next_value(&mut args, &arg)?, &arg)?;
+                    if config.halving_interval == 0 {
+                        return Err(AppError::Usage(
+                            "--halving-interval must be at least 1 block".to_string(),
+                        ));
+                    }
+                }
+                "--watch" => config.watch = Some(next_value(&mut args, &arg)?),
+                "--watch-interval" => {
+                    config.watch_interval = parse_value(&next_value(&mut args, &arg)?, &arg)?;
+                    if config.watch_interval == 0 {
+                        return Err(AppError::Usage(
+                            "--watch-interval must be at least 1 second".to_string(),
+                        ));
+                    }
+                }
+                "--dump-mempool" => {
+                    config.dump_mempool = Some(PathBuf::from(next_value(&mut args, &arg)?))
+                }
+                "--load-mempool" => {
+                    config.load_mempool = Some(PathBuf::from(next_value(&mut args, &arg)?))
+                }
+                "--warmup-feeest" => config.warmup_feeest = true,
+                "--include-mempool" => {
+                    config.include_mempool = parse_value(&next_value(&mut args, &arg)?, &arg)?
+                }
+                "--pay" => config.pay = Some(next_value(&mut args, &arg)?.parse()?),
+                "--subtract-fee-from" => {
+                    let value = next_value(&mut args, &arg)?;
+                    config.subtract_fee_from = Some(if value == "all" {
+                        SubtractFeeFrom::All
+                    } else {
+                        let indices = value
+                            .split(',')
+                            .map(|i| parse_value(i.trim(), &arg))
+                            .collect::<Result<_, _>>()?;
+                        SubtractFeeFrom::Indices(indices)
+                    });
+                }
+                "--export-env" => {
+                    config.export_env = Some(PathBuf::from(next_value(&mut args, &arg)?))
+                }
+                "--include-secrets" => config.include_secrets = true,
+                "--verify-report" => config.verify_report = true,
+                "--demo-mempool-pressure" => {
+                    let kvb: usize = parse_value(&next_value(&mut args, &arg)?, &arg)?;
+                    if kvb == 0 {
+                        return Err(AppError::Usage(
+                            "--demo-mempool-pressure must be at least 1 kvB".to_string(),
+                        ));
+                    }
+                    config.demo_mempool_pressure = Some(kvb);
+                }
+                "--no-change-expected" => config.no_change_expected = true,
+                "--analyze-block" => config.analyze_block = true,
+                "--diff" => config.diff = Some(PathBuf::from(next_value(&mut args, &arg)?)),
+                "--strict" => config.strict = true,
+                "--explain" => config.explain = true,
+                "--reconcile" => config.reconcile = true,
+                "--allow-reuse" => config.allow_reuse = true,
+                "--privacy-analysis" => config.privacy_analysis = true,
+                "--analyze" => config
+                    .analyze_txids
+                    .push(parse_value(&next_value(&mut args, &arg)?, &arg)?),
+                "--workers" => {
+                    config.workers = parse_value(&next_value(&mut args, &arg)?, &arg)?;
+                    if config.workers == 0 {
+                        return Err(AppError::Usage("--workers must be at least 1".to_string()));
+                    }
+                }
+                "--attempts" => {
+                    config.attempts = parse_value(&next_value(&mut args, &arg)?, &arg)?;
+                    if config.attempts == 0 {
+                        return Err(AppError::Usage("--attempts must be at least 1".to_string()));
+                    }
+                }
+                "--fixture" => config.fixture = Some(PathBuf::from(next_value(&mut args, &arg)?)),
+                #[cfg(feature = "offline-sign")]
+                "--offline-sign" => {
+                    config.offline_sign = Some(PathBuf::from(next_value(&mut args, &arg)?))
+                }
+                #[cfg(feature = "offline-sign")]
+                "--offline-broadcast" => config.offline_broadcast = true,
+                "--from-fixture" => {
+                    config.from_fixture = Some(PathBuf::from(next_value(&mut args, &arg)?))
+                }
+                "--balance-assert" => {
+                    let value = next_value(&mut args, &arg)?;
+                    config.balance_assert = Some(amount::parse_amount(&value, Unit::Btc)?);
+                }
+                "--since-height" => {
+                    config.since_height = Some(parse_value(&next_value(&mut args, &arg)?, &arg)?)
+                }
+                "--confirm-depth" => {
+                    config.confirm_depth = parse_value(&next_value(&mut args, &arg)?, &arg)?;
+                    if config.confirm_depth == 0 {
+                        return Err(AppError::Usage(
+                            "--confirm-depth must be at least 1".to_string(),
+                        ));
+                    }
+                }
+                "--rpc-budget" => {
+                    let value = next_value(&mut args, &arg)?;
+                    config.rpc_budget = Some(match value.as_str() {
+                        "baseline" => rpc::BASELINE_CALL_BUDGET,
+                        _ => parse_value(&value, &arg)?,
+                    })
+                }
+                "--mine-to" => {
+                    let value = next_value(&mut args, &arg)?;
+                    let address = Address::from_str(&value).map_err(|e| {
+                        AppError::Usage(format!("invalid --mine-to address '{value}': {e}"))
+                    })?;
+                    config.mine_to = Some(address);
+                }
+                other => return Err(AppError::Usage(format!("unknown option '{other}'"))),
+            }
+        }
+
+        if !config.sendall
+            && (!config.sendall_inputs.is_empty() || !config.sendall_recipients.is_empty())
+        {
+            return Err(AppError::Usage(
+                "--sendall-input and --sendall-to require --sendall".to_string(),
+            ));
+        }
+        if config.sendall && config.send_rpc {
+            return Err(AppError::Usage(
+                "--sendall and --send-rpc are mutually exclusive".to_string(),
+            ));
+        }
+        if (config.sendall || config.send_rpc) && !config.recipients.is_empty() {
+            return Err(AppError::Usage(
+                "--recipient cannot be combined with --sendall or --send-rpc".to_string(),
+            ));
+        }
+        if config.pay.is_some() && (config.sendall || !config.recipients.is_empty()) {
+            return Err(AppError::Usage(
+                "--pay cannot be combined with --sendall or --recipient".to_string(),
+            ));
+        }
+        // Consolidation replaces the Trader payment, so no payment option applies
+        if config.consolidate
+            && (config.sendall
+                || config.send_rpc
+                || config.pay.is_some()
+                || !config.recipients.is_empty())
+        {
+            return Err(AppError::Usage(
+                "--consolidate cannot be combined with --sendall, --send-rpc, --pay or --recipient"
+                    .to_string(),
+            ));
+        }
+        // The preview funds a single payment to the Trader
+        if (config.simulate || config.dry_run)
+            && (config.sendall || config.consolidate || !config.recipients.is_empty())
+        {
+            return Err(AppError::Usage(
+                "--simulate and --dry-run cannot be combined with --sendall, --consolidate or --recipient"
+                    .to_string(),
+            ));
+        }
+        // Both read reports back assuming the standard ten lines
+        if config.extended_report && (config.verify_report || config.diff.is_some()) {
+            return Err(AppError::Usage(
+                "--extended-report cannot be combined with --verify-report or --diff".to_string(),
+            ));
+        }
+        if config.deterministic_fee.is_some()
+            && (config.sendall || config.send_rpc || !config.recipients.is_empty())
+        {
+            return Err(AppError::Usage(
+                "--deterministic-fee cannot be combined with --sendall, --send-rpc or --recipient"
+                    .to_string(),
+            ));
+        }
+        if let Some(SubtractFeeFrom::Indices(indices)) = &config.subtract_fee_from {
+            // Without --recipient the only output is the Trader payment at index 0
+            let count = config.recipients.len().max(1);
+            if let Some(bad) = indices.iter().find(|&&i| i >= count) {
+                return Err(AppError::Usage(format!(
+                    "--subtract-fee-from index {bad} is out of range for {count} recipient(s)"
+                )));
+            }
+        }
+        if config.subtract_fee_from.is_some() && (config.sendall || config.send_rpc) {
+            return Err(AppError::Usage(
+                "--subtract-fee-from applies to sendtoaddress and --recipient payments only"
+                    .to_string(),
+            ));
+        }
+        if config.verify_report && config.format != ReportFormat::Txt {
+            return Err(AppError::Usage(
+                "--verify-report checks out.txt and requires --format txt".to_string(),
+            ));
+        }
+        if config.no_change_expected && !config.sendall && config.subtract_fee_from.is_none() {
+            return Err(AppError::Usage(
+                "--no-change-expected needs an exact spend: --sendall or --subtract-fee-from"
+                    .to_string(),
+            ));
+        }
+        if config.miner_wallet == config.trader_wallet {
+            return Err(AppError::Usage(
+                "--miner-wallet and --trader-wallet must name different wallets".to_string(),
+            ));
+        }
+        if config.strict && config.diff.is_none() {
+            return Err(AppError::Usage("--strict requires --diff".to_string()));
+        }
+        #[cfg(feature = "offline-sign")]
+        if config.offline_broadcast && config.offline_sign.is_none() {
+            return Err(AppError::Usage(
+                "--offline-broadcast requires --offline-sign".to_string(),
+            ));
+        }
+        if config.include_secrets && config.export_env.is_none() {
+            return Err(AppError::Usage(
+                "--include-secrets requires --export-env".to_string(),
+            ));
+        }
+        Ok(config)
+    }
+
+    // Write the node connection and every option of this run as KEY=value
+    // lines. Sourcing the file with `set -a` and running without arguments
+    // repeats the run, since from_args reads the same variables back. The RPC
+    // password is commented out unless --include-secrets was given.
+    pub fn to_env_file(&self, path: &Path) -> Result<(), AppError> {
+        let lines = [
+            format!("{RPC_URL_VAR}={}", shell_quote(&self.rpc_url)),
+            format!("{RPC_USER_VAR}={}", shell_quote(&self.rpc_user)),
+            if self.include_secrets {
+                format!("{RPC_PASSWORD_VAR}={}", shell_quote(&self.rpc_password))
+            } else {
+                format!("# {RPC_PASSWORD_VAR}=<redacted, rerun with --include-secrets>")
+            },
+            // One option per line, which a single-quoted shell value may span
+            format!("{ARGS_VAR}={}", shell_quote(&self.args.join("\n"))),
+            String::new(),
+        ];
+        fs::write(path, lines.join("\n"))?;
+        Ok(())
+    }
+}
+
+// `args` without --export-env and its value or --include-secrets, which only
+// shape the env file and would make its replay write another one
+fn without_export_options(args: &[String]) -> Vec<String> {
+    let mut kept = Vec::with_capacity(args.len());
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--export-env" => {
+                args.next();
+            }
+            "--include-secrets" => {}
+            _ => kept.push(arg.clone()),
+        }
+    }
+    kept
+}
+
+// Quote a value for POSIX shells: wrap it in single quotes, closing and
+// reopening around any single quote it contains
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+// Take the value following a flag such as `--sendall-to <address>`
+fn next_value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String, AppError> {
+    args.next()
+        .ok_or_else(|| AppError::Usage(format!("{flag} requires a value")))
+}
+
+// Parse a flag's value, naming the flag in the error
+fn parse_value<T>(value: &str, flag: &str) -> Result<T, AppError>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|e| AppError::Usage(format!("invalid value '{value}' for {flag}: {e}")))
+}
+
+// Accept either a release ("24.0.1", "0.21.1") or the numeric form
+// getnetworkinfo reports ("240001", "210100")
+fn parse_core_version(value: &str) -> Result<usize, AppError> {
+    if !value.contains('.') {
+        return parse_value(value, "--core-version");
+    }
+    let parts = value
+        .split('.')
+        .map(|p| parse_value::<usize>(p, "--core-version"))
+        .collect::<Result<Vec<_>, _>>()?;
+    let part = |i: usize| parts.get(i).copied().unwrap_or(0);
+    // Releases before 22.0 were numbered 0.MAJOR.MINOR
+    Ok(if part(0) == 0 {
+        part(1) * 10_000 + part(2) * 100
+    } else {
+        part(0) * 10_000 + part(1) * 100 + part(2)
+    })
+}
+
+// Address types accepted by getnewaddress
+fn parse_address_type(value: &str) -> Result<AddressType, AppError> {
+    match value {
+        "legacy" => Ok(AddressType::Legacy),
+        "p2sh-segwit" => Ok(AddressType::P2shSegwit),
+        "bech32" => Ok(AddressType::Bech32),
+        "bech32m" => Ok(AddressType::Bech32m),
+        other => Err(AppError::Usage(format!(
+            "invalid --address-type '{other}' (expected legacy, p2sh-segwit, bech32 or bech32m)"
+        ))),
+    }
+}
+
+// A recipient given as ADDRESS:AMOUNT, with the amount in BTC
+fn parse_recipient(value: &str) -> Result<(String, Amount), AppError> {
+    let Some((address, amount)) = value.rsplit_once(':') else {
+        return Err(AppError::Usage(format!(
+            "invalid --recipient '{value}' (expected ADDRESS:AMOUNT)"
+        )));
+    };
+    let amount = amount::parse_amount(amount, Unit::Btc)
+        .map_err(|e| AppError::Usage(format!("invalid amount in --recipient '{value}': {e}")))?;
+    Ok((address.to_string(), amount))
+}
@@ -0,0 +1,264 @@
+// Error type shared by every phase of the run
+use bitcoincore_rpc::bitcoin::amount::ParseAmountError;
+use bitcoincore_rpc::bitcoin::consensus::encode;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum AppError {
+    /// An RPC call to the node failed
+    Rpc(bitcoincore_rpc::Error),
+    /// An RPC call failed, named with its (redacted) arguments
+    RpcCall {
+        method: String,
+        params: String,
+        source: bitcoincore_rpc::Error,
+    },
+    /// Reading or writing a local file failed
+    Io(std::io::Error),
+    /// An amount could not be represented in satoshis
+    Amount(ParseAmountError),
+    /// The command line could not be parsed
+    Usage(String),
+    /// The node offers no way to perform the requested operation
+    Unsupported(String),
+    /// Mining hit the block cap without producing a spendable balance
+    MiningStalled { blocks: u64 },
+    /// Data returned by the node could not be decoded locally
+    Decode(String),
+    /// The txid recomputed from the raw transaction differs from the node's
+    TxidMismatch {
+        reported: String,
+        recomputed: String,
+    },
+    /// The wallet's balance did not follow the coinbase maturity rule
+    MaturityViolation(String),
+    /// The user declined to continue
+    Cancelled(String),
+    /// Some transactions in an --analyze batch could not be analysed
+    BatchFailed { failed: usize, total: usize },
+    /// Replaying a fixture produced a report that differs from the recorded one
+    FixtureMismatch(usize),
+    /// A send would pay an address that was already paid in this run
+    AddressReuse(String),
+    /// A wallet's balance disagrees with the UTXOs it lists
+    BalanceMismatch(String),
+    /// An exact spend still produced a change output
+    UnexpectedChange(String),
+    /// The mempool demonstration funded fewer filler coins than it needs
+    FanOutShort { created: usize, wanted: usize },
+    /// The connection running rescanblockchain gave up while the node scans on
+    RescanStillRunning { progress: f32 },
+    /// --consolidate left non-coinbase UTXOs other than the consolidated one
+    ConsolidationIncomplete(usize),
+    /// The run made more RPC round-trips than --rpc-budget (or, for a run
+    /// with no options, the baseline budget) allows
+    RpcBudgetExceeded { calls: usize, budget: usize },
+    /// The report's block hash is not the node's block at the report's height
+    BlockHashMismatch {
+        height: u64,
+        recorded: String,
+        expected: String,
+    },
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Rpc(e) => write!(f, "RPC error: {e}"),
+            AppError::RpcCall {
+                method,
+                params,
+                source,
+            } => write!(f, "{method}({params}) failed: {source}"),
+            AppError::Io(e) => write!(f, "I/O error: {e}"),
+            AppError::Amount(e) => write!(f, "invalid amount: {e}"),
+            AppError::Usage(msg) => write!(f, "usage: {msg}"),
+            AppError::Unsupported(msg) => write!(f, "unsupported: {msg}"),
+            AppError::MiningStalled { blocks } => {
+                write!(f, "mined {blocks} blocks without a spendable balance")
+            }
+            AppError::Decode(msg) => write!(f, "decode error: {msg}"),
+            AppError::TxidMismatch {
+                reported,
+                recomputed,
+            } => write!(
+                f,
+                "txid mismatch: node reported {reported}, raw bytes hash to {recomputed}"
+            ),
+            AppError::MaturityViolation(msg) => write!(f, "coinbase maturity check failed: {msg}"),
+            AppError::Cancelled(msg) => write!(f, "cancelled: {msg}"),
+            AppError::BatchFailed { failed, total } => {
+                write!(f, "{failed} of {total} transactions could not be analysed")
+            }
+            AppError::FixtureMismatch(fields) => {
+                write!(
+                    f,
+                    "replayed report differs from the fixture in {fields} field(s)"
+                )
+            }
+            AppError::AddressReuse(address) => write!(
+                f,
+                "{address} was already paid in this run (pass --allow-reuse to send anyway)"
+            ),
+            AppError::BalanceMismatch(msg) => write!(f, "balance does not reconcile: {msg}"),
+            AppError::UnexpectedChange(msg) => write!(f, "unexpected change output: {msg}"),
+            AppError::FanOutShort { created, wanted } => {
+                write!(f, "fan-out created {created} of the {wanted} filler coins")
+            }
+            AppError::RescanStillRunning { progress } => write!(
+                f,
+                "the node is still rescanning ({:.0}% done); poll getwalletinfo until it finishes instead of starting another",
+                progress * 100.0
+            ),
+            AppError::ConsolidationIncomplete(utxos) => {
+                write!(
+                    f,
+                    "consolidation left {utxos} non-coinbase UTXOs instead of one"
+                )
+            }
+            AppError::RpcBudgetExceeded { calls, budget } => {
+                write!(f, "made {calls} RPC calls, over the budget of {budget}")
+            }
+            AppError::BlockHashMismatch {
+                height,
+                recorded,
+                expected,
+            } => write!(
+                f,
+                "block hash mismatch at height {height}: report has {recorded}, node has {expected}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AppError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AppError::Rpc(e) | AppError::RpcCall { source: e, .. } => Some(e),
+            AppError::Io(e) => Some(e),
+            AppError::Amount(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+// Calls whose arguments carry passphrases or private keys are never echoed
+const SECRET_METHODS: [&str; 8] = [
+    "walletpassphrase",
+    "walletpassphrasechange",
+    "encryptwallet",
+    "createwallet",
+    "dumpprivkey",
+    "importprivkey",
+    "importdescriptors",
+    "signrawtransactionwithkey",
+];
+
+// Arguments longer than this, usually raw transaction hex, are shortened
+const MAX_PARAM_LEN: usize = 32;
+
+impl AppError {
+    // Attach the failing method and its arguments to an RPC error
+    pub fn rpc_call(method: &str, params: &[String], source: bitcoincore_rpc::Error) -> Self {
+        let params = if SECRET_METHODS.contains(&method) {
+            "<redacted>".to_string()
+        } else {
+            params
+                .iter()
+                .map(|p| {
+                    if p.chars().count() > MAX_PARAM_LEN {
+                        format!("{}...", p.chars().take(MAX_PARAM_LEN).collect::<String>())
+                    } else {
+                        p.clone()
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        AppError::RpcCall {
+            method: method.to_string(),
+            params,
+            source,
+        }
+    }
+
+    // The node error behind an RPC failure, with or without call context
+    pub fn rpc_source(&self) -> Option<&bitcoincore_rpc::Error> {
+        match self {
+            AppError::Rpc(e) | AppError::RpcCall { source: e, .. } => Some(e),
+            _ => None,
+        }
+    }
+}
+
+// Every node connection records the request it sent last, so an RPC error is
+// named with the call behind it wherever it is propagated with `?`
+impl From<bitcoincore_rpc::Error> for AppError {
+    fn from(e: bitcoincore_rpc::Error) -> Self {
+        match crate::rpc::take_last_request() {
+            Some(sent) => AppError::rpc_call(&sent.method, &sent.params, e),
+            None => AppError::Rpc(e),
+        }
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(e: std::io::Error) -> Self {
+        AppError::Io(e)
+    }
+}
+
+impl From<ParseAmountError> for AppError {
+    fn from(e: ParseAmountError) -> Self {
+        AppError::Amount(e)
+    }
+}
+
+impl From<encode::Error> for AppError {
+    fn from(e: encode::Error) -> Self {
+        AppError::Rpc(e.into())
+    }
+}
+
+// Whether a failure may clear up if the whole run is tried again: the node
+// was unreachable, still starting up, or busy loading a wallet. Anything the
+// node actually refused, such as insufficient funds, is final.
+pub fn is_retryable(e: &AppError) -> bool {
+    use bitcoincore_rpc::jsonrpc::error::Error as JsonRpcError;
+    use std::io::ErrorKind;
+
+    let io_transient = |e: &std::io::Error| {
+        matches!(
+            e.kind(),
+            ErrorKind::ConnectionRefused
+                | ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::TimedOut
+                | ErrorKind::UnexpectedEof
+        )
+    };
+    if let AppError::Io(e) = e {
+        return io_transient(e);
+    }
+    match e.rpc_source() {
+        Some(bitcoincore_rpc::Error::Io(e)) => io_transient(e),
+        Some(bitcoincore_rpc::Error::JsonRpc(JsonRpcError::Transport(_))) => true,
+        // -28 RPC_IN_WARMUP, -18 RPC_WALLET_NOT_FOUND (unloaded underneath us),
+        // -4 with "loading" while another client is still loading the wallet
+        Some(bitcoincore_rpc::Error::JsonRpc(JsonRpcError::Rpc(rpc))) => {
+            rpc.code == -28
+                || rpc.code == -18
+                || (rpc.code == -4 && rpc.message.contains("loading"))
+        }
+        _ => false,
+    }
+}
+
+// Whether the node rejected a call because it does not know the method
+pub fn is_method_not_found(e: &bitcoincore_rpc::Error) -> bool {
+    matches!(
+        e,
+        bitcoincore_rpc::Error::JsonRpc(bitcoincore_rpc::jsonrpc::error::Error::Rpc(rpc))
+            if rpc.code == -32601
+    )
+}
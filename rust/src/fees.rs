@@ -0,0 +1,299 @@
+// Fee rate helpers and fee/confirmation-time tradeoffs
+use crate::error::AppError;
+use crate::logging::info;
+use crate::wallet::SendToAddressOptions;
+use bitcoincore_rpc::bitcoin::{Address, Amount, BlockHash, Network, Txid};
+use bitcoincore_rpc::json::{
+    AddressType, DecodeRawTransactionResult, GetBlockStatsResultPartial, GetRawTransactionResultVin,
+};
+use bitcoincore_rpc::{Client, RpcApi};
+use serde::Deserialize;
+use serde_json::json;
+use std::fmt;
+
+// Confirmation targets probed when inverting estimatesmartfee, in blocks
+const ESTIMATE_TARGETS: [u16; 10] = [1, 2, 3, 6, 12, 24, 48, 144, 504, 1008];
+
+// Roughly how long a transaction paying a given fee rate waits for a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfirmationEstimate {
+    /// Regtest blocks are mined on demand, so confirmation is immediate
+    Immediate,
+    /// The fee rate meets the node's estimate for this many blocks
+    WithinBlocks(u16),
+    /// The fee rate is below every estimate, or the node has no fee history
+    Unknown,
+}
+
+impl fmt::Display for ConfirmationEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfirmationEstimate::Immediate => write!(f, "immediate (regtest mines on demand)"),
+            ConfirmationEstimate::WithinBlocks(1) => write!(f, "next block"),
+            ConfirmationEstimate::WithinBlocks(n) => write!(f, "within ~{n} blocks"),
+            ConfirmationEstimate::Unknown => write!(f, "unknown (no matching fee estimate)"),
+        }
+    }
+}
+
+// Fee rate in sat/vB for a fee paid by a transaction of `vsize` virtual bytes
+pub fn fee_rate_sat_vb(fee: Amount, vsize: u32) -> f64 {
+    if vsize == 0 {
+        return 0.0;
+    }
+    fee.to_sat() as f64 / f64::from(vsize)
+}
+
+// Paying more than this multiple of the block's median fee rate is treated as
+// a probable mistake rather than a deliberate priority bid
+pub const ABSURD_FEE_MULTIPLE: f64 = 10.0;
+
+// Largest fee the transaction pays at `max_feerate` sat/vB: the rate applied
+// to its weight in virtual bytes (weight / 4, rounded up)
+pub fn theoretical_max_fee(decoded_tx: &DecodeRawTransactionResult, max_feerate: f64) -> Amount {
+    let vbytes = u64::from(decoded_tx.weight).div_ceil(4);
+    Amount::from_sat((vbytes as f64 * max_feerate).ceil() as u64)
+}
+
+// Map a fee rate (sat/vB) to the shortest confirmation target whose
+// estimatesmartfee rate it meets
+pub fn estimate_confirmation(
+    rpc: &Client,
+    network: Network,
+    fee_rate: f64,
+) -> Result<ConfirmationEstimate, AppError> {
+    if network == Network::Regtest {
+        return Ok(ConfirmationEstimate::Immediate);
+    }
+
+    for target in ESTIMATE_TARGETS {
+        let estimate = rpc.estimate_smart_fee(target, None)?;
+        // estimatesmartfee reports BTC/kvB; 1 sat/vB == 1000 sat/kvB
+        if let Some(per_kvb) = estimate.fee_rate {
+            if fee_rate >= per_kvb.to_sat() as f64 / 1000.0 {
+                let blocks = u16::try_from(estimate.blocks).unwrap_or(target);
+                return Ok(ConfirmationEstimate::WithinBlocks(blocks));
+            }
+        }
+    }
+    Ok(ConfirmationEstimate::Unknown)
+}
+
+// Estimator warm-up: blocks mined, and self-payments confirmed in each, at
+// fee rates cycling through WARMUP_RATES (sat/vB)
+const WARMUP_BLOCKS: usize = 30;
+const WARMUP_TXS_PER_BLOCK: usize = 6;
+const WARMUP_RATES: [f64; 6] = [2.0, 4.0, 6.0, 10.0, 15.0, 25.0];
+const WARMUP_PAYMENT: Amount = Amount::from_sat(100_000);
+
+// Targets shown before and after the warm-up
+const WARMUP_TARGETS: [u16; 3] = [2, 6, 12];
+
+// Print estimatesmartfee for a few targets, or the reason it has no answer
+pub fn print_estimates(rpc: &Client, title: &str) -> Result<(), AppError> {
+    info!("{title}");
+    for target in WARMUP_TARGETS {
+        let estimate = rpc.estimate_smart_fee(target, None)?;
+        match estimate.fee_rate {
+            Some(per_kvb) => info!(
+                "  {target:>2} blocks: {:.2} sat/vB",
+                per_kvb.to_sat() as f64 / 1000.0
+            ),
+            None => info!(
+                "  {target:>2} blocks: no estimate ({})",
+                estimate
+                    .errors
+                    .map(|errors| errors.join("; "))
+                    .unwrap_or_else(|| "no reason given".to_string())
+            ),
+        }
+    }
+    Ok(())
+}
+
+// Give a fresh regtest node the fee history estimatesmartfee needs. The
+// estimator learns only from transactions it saw in the mempool and then in a
+// block, so each round sends a few self-payments at varied rates and mines
+// them. Returns how many transactions were sent.
+pub fn warm_up_estimator(rpc: &Client, mine_to: &Address) -> Result<usize, AppError> {
+    let mut sent = 0;
+    for _ in 0..WARMUP_BLOCKS {
+        for _ in 0..WARMUP_TXS_PER_BLOCK {
+            let rate = WARMUP_RATES[sent % WARMUP_RATES.len()];
+            let address = rpc
+                .get_new_address(None, Some(AddressType::Bech32))?
+                .assume_checked();
+            SendToAddressOptions::new()
+                .fee_rate(rate)
+                .send(rpc, &address, WARMUP_PAYMENT)?;
+            sent += 1;
+        }
+        rpc.generate_to_address(1, mine_to)?;
+    }
+    Ok(sent)
+}
+
+// Portion of the fee attributable to one input's size
+#[derive(Debug, Clone, Copy)]
+pub struct InputFeeShare {
+    pub index: usize,
+    /// Virtual size of the input, witness included
+    pub vbytes: f64,
+    pub fee: Amount,
+}
+
+// Length of a Bitcoin CompactSize integer
+fn compact_size_len(n: usize) -> usize {
+    match n {
+        0..=0xfc => 1,
+        0xfd..=0xffff => 3,
+        0x1_0000..=0xffff_ffff => 5,
+        _ => 9,
+    }
+}
+
+// Weight of an input: outpoint, scriptSig and sequence count four units per
+// byte, witness data one unit per byte
+fn input_weight(input: &GetRawTransactionResultVin) -> usize {
+    let script_sig = input
+        .script_sig
+        .as_ref()
+        .map(|s| s.hex.len())
+        .or_else(|| input.coinbase.as_ref().map(Vec::len))
+        .unwrap_or(0);
+    let base = 32 + 4 + compact_size_len(script_sig) + script_sig + 4;
+    let witness = input.txinwitness.as_ref().map_or(0, |items| {
+        compact_size_len(items.len())
+            + items
+                .iter()
+                .map(|i| compact_size_len(i.len()) + i.len())
+                .sum::<usize>()
+    });
+    base * 4 + witness
+}
+
+// Split the fee across inputs in proportion to each input's share of the
+// transaction's vsize. The remainder pays for outputs and the fixed header,
+// which is why spending many small inputs costs more than spending one.
+pub fn per_input_fee_share(
+    decoded_tx: &DecodeRawTransactionResult,
+    total_fee: Amount,
+) -> Vec<InputFeeShare> {
+    let vsize = f64::from(decoded_tx.vsize.max(1));
+    decoded_tx
+        .vin
+        .iter()
+        .enumerate()
+        .map(|(index, input)| {
+            let vbytes = input_weight(input) as f64 / 4.0;
+            let fee = (total_fee.to_sat() as f64 * vbytes / vsize).round() as u64;
+            InputFeeShare {
+                index,
+                vbytes,
+                fee: Amount::from_sat(fee),
+            }
+        })
+        .collect()
+}
+
+// Where a transaction's fee rate falls among the others in its block.
+// getblockstats reports every rate in whole sat/vB.
+#[derive(Debug, Clone, Copy)]
+pub struct BlockFeeRank {
+    pub fee_rate: f64,
+    pub min_rate: u64,
+    pub median_rate: u64,
+    pub max_rate: u64,
+    /// Highest of the 10th/25th/50th/75th/90th percentiles the rate reaches;
+    /// 0 when it is below the 10th
+    pub percentile: u8,
+}
+
+impl fmt::Display for BlockFeeRank {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let median = self.median_rate as f64;
+        let side = if self.fee_rate > median {
+            "above"
+        } else if self.fee_rate < median {
+            "below"
+        } else {
+            "at"
+        };
+        write!(
+            f,
+            "{:.2} sat/vB, {side} the block median of {} sat/vB (block range {}-{} sat/vB",
+            self.fee_rate, self.median_rate, self.min_rate, self.max_rate
+        )?;
+        match self.percentile {
+            0 => write!(f, ", under the 10th percentile)"),
+            p => write!(f, ", at least the {p}th percentile)"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct VerboseBlock {
+    tx: Vec<VerboseBlockTx>,
+}
+
+#[derive(Deserialize)]
+struct VerboseBlockTx {
+    txid: Txid,
+    vsize: u32,
+    /// Absent for the coinbase, and on nodes without the block's undo data
+    #[serde(default, with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc::opt")]
+    fee: Option<Amount>,
+}
+
+// Rank `txid`'s fee rate against the rest of `block_hash`. Returns None for a
+// block holding only its coinbase, which has no fee rates to compare with.
+pub fn feerate_percentile(
+    rpc: &Client,
+    txid: &Txid,
+    block_hash: &BlockHash,
+) -> Result<Option<BlockFeeRank>, AppError> {
+    let block: VerboseBlock = rpc.call("getblock", &[json!(block_hash), json!(2)])?;
+    if block.tx.len() < 2 {
+        return Ok(None);
+    }
+    let tx = block
+        .tx
+        .iter()
+        .find(|tx| tx.txid == *txid)
+        .ok_or_else(|| AppError::Unsupported(format!("{txid} is not in block {block_hash}")))?;
+    let fee = tx.fee.ok_or_else(|| {
+        AppError::Unsupported(format!(
+            "the node reports no fee for {txid}; its block undo data may be pruned"
+        ))
+    })?;
+    let fee_rate = fee_rate_sat_vb(fee, tx.vsize);
+
+    let stats: GetBlockStatsResultPartial = rpc.call(
+        "getblockstats",
+        &[
+            json!(block_hash),
+            json!(["minfeerate", "maxfeerate", "feerate_percentiles"]),
+        ],
+    )?;
+    let missing = || AppError::Decode("getblockstats omitted fee rate fields".to_string());
+    let percentiles = stats.fee_rate_percentiles.ok_or_else(missing)?;
+    let percentile = [
+        (10, percentiles.fr_10th),
+        (25, percentiles.fr_25th),
+        (50, percentiles.fr_50th),
+        (75, percentiles.fr_75th),
+        (90, percentiles.fr_90th),
+    ]
+    .into_iter()
+    .filter(|(_, rate)| fee_rate >= rate.to_sat() as f64)
+    .map(|(p, _)| p)
+    .max()
+    .unwrap_or(0);
+    Ok(Some(BlockFeeRank {
+        fee_rate,
+        min_rate: stats.min_fee_rate.ok_or_else(missing)?.to_sat(),
+        median_rate: percentiles.fr_50th.to_sat(),
+        max_rate: stats.max_fee_rate.ok_or_else(missing)?.to_sat(),
+        percentile,
+    }))
+}
@@ -0,0 +1,244 @@
+// Self-contained fixtures for replaying the transaction analysis without a node.
+// A fixture directory holds:
+//   tx.hex               the analysed transaction
+//   prevouts/<txid>.hex  every transaction it spends from
+//   header.hex           header of the confirming block
+//   fixture.json         network, block height, and the Trader and change addresses
+//   expected.txt         the ten-line report the live run produced
+// fixtures/regtest-send is a small example that the tests replay.
+use crate::analysis::{self, ChangeOutput, InputDetail, InputSummary, Owner, TxOutput};
+use crate::error::AppError;
+use crate::logging::info;
+use crate::report::{self, TransactionReport};
+use bitcoincore_rpc::bitcoin::block::Header;
+use bitcoincore_rpc::bitcoin::consensus::{deserialize, serialize};
+use bitcoincore_rpc::bitcoin::hashes::Hash;
+use bitcoincore_rpc::bitcoin::{Address, Network, Script, Transaction, Txid};
+use bitcoincore_rpc::{Client, RpcApi};
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+
+// Write everything needed to replay `report` into `dir`
+pub fn capture(
+    rpc: &Client,
+    dir: &Path,
+    network: Network,
+    raw_tx: &str,
+    report: &TransactionReport,
+    changes: &[ChangeOutput],
+) -> Result<(), AppError> {
+    let prevout_dir = dir.join("prevouts");
+    fs::create_dir_all(&prevout_dir)?;
+    fs::write(dir.join("tx.hex"), raw_tx)?;
+
+    let tx: Transaction = decode_hex(raw_tx)?;
+    if !tx.is_coinbase() {
+        for input in &tx.input {
+            let txid = input.previous_output.txid;
+            let hex = rpc.get_raw_transaction_hex(&txid, None)?;
+            fs::write(prevout_dir.join(format!("{txid}.hex")), hex)?;
+        }
+    }
+
+    let header = rpc.get_block_header(&report.block_hash)?;
+    fs::write(dir.join("header.hex"), hex::encode(serialize(&header)))?;
+
+    let meta = json!({
+        "network": network.to_string(),
+        "block_height": report.block_height,
+        "trader_address": report.trader_address,
+        "change_address": report.change_address,
+        "change_addresses": changes.iter().map(|c| &c.address).collect::<Vec<_>>(),
+    });
+    fs::write(dir.join("fixture.json"), format!("{meta:#}\n"))?;
+
+    // The replay can only rebuild the standard fields
+    let standard = TransactionReport {
+        confirmations: None,
+        ..report.clone()
+    };
+    report::write_report(&standard, dir, report::ReportFormat::Txt, false)?;
+    fs::rename(dir.join("out.txt"), dir.join("expected.txt"))?;
+    Ok(())
+}
+
+// Rebuild the report from the fixture files alone and compare it with the
+// expected one, failing on any difference
+pub fn replay(dir: &Path) -> Result<TransactionReport, AppError> {
+    let meta: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(dir.join("fixture.json"))?)
+            .map_err(|e| AppError::Decode(format!("invalid fixture.json: {e}")))?;
+    let field = |name: &str| {
+        meta[name]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| AppError::Decode(format!("fixture.json lacks {name}")))
+    };
+    let network: Network = field("network")?
+        .parse()
+        .map_err(|e| AppError::Decode(format!("invalid network in fixture.json: {e}")))?;
+    let block_height = meta["block_height"]
+        .as_u64()
+        .ok_or_else(|| AppError::Decode("fixture.json lacks block_height".to_string()))?;
+    let trader_address = field("trader_address")?;
+    let change_address = field("change_address")?;
+    // Fixtures from before change could be split name only the first address
+    let change_addresses: Vec<String> = match meta["change_addresses"].as_array() {
+        Some(addresses) => addresses
+            .iter()
+            .filter_map(|a| a.as_str().map(str::to_string))
+            .collect(),
+        None => vec![change_address.clone()],
+    };
+
+    let tx_hex = fs::read_to_string(dir.join("tx.hex"))?;
+    let tx: Transaction = decode_hex(tx_hex.trim())?;
+    let header: Header = decode_hex(fs::read_to_string(dir.join("header.hex"))?.trim())?;
+
+    // Same rules as the live analysis: coinbase inputs count as the subsidy
+    let mut inputs = Vec::with_capacity(tx.input.len());
+    for input in &tx.input {
+        if tx.is_coinbase() {
+            inputs.push(InputDetail {
+                address: String::new(),
+                amount: analysis::block_subsidy(block_height),
+                coinbase: true,
+                script_type: None,
+            });
+            continue;
+        }
+        let outpoint = input.previous_output;
+        let path = dir.join("prevouts").join(format!("{}.hex", outpoint.txid));
+        let prev: Transaction = decode_hex(fs::read_to_string(&path)?.trim())?;
+        let spent = prev.output.get(outpoint.vout as usize).ok_or_else(|| {
+            AppError::Decode(format!(
+                "{} has no output {}",
+                path.display(),
+                outpoint.vout
+            ))
+        })?;
+        inputs.push(InputDetail {
+            address: address_of(&spent.script_pubkey, network),
+            amount: spent.value,
+            coinbase: false,
+            script_type: None,
+        });
+    }
+    let summary = InputSummary {
+        total: inputs.iter().map(|i| i.amount).sum(),
+        inputs,
+    };
+
+    // The stored addresses stand in for asking the wallets who owns what
+    let outputs: Vec<TxOutput> = tx
+        .output
+        .iter()
+        .enumerate()
+        .map(|(vout, output)| TxOutput {
+            vout: vout as u32,
+            address: address_of(&output.script_pubkey, network),
+            amount: output.value,
+        })
+        .collect();
+    let split = analysis::classify_outputs(&outputs, |address| {
+        if address == trader_address {
+            Some(Owner::Trader)
+        } else if change_addresses.iter().any(|a| a == address) {
+            Some(Owner::Miner)
+        } else {
+            None
+        }
+    });
+    let report = analysis::build_report(
+        compute_txid(tx_hex.trim())?,
+        &summary,
+        &outputs,
+        &split,
+        block_height as usize,
+        header.block_hash(),
+    );
+
+    let expected = report::read_report_lines(&dir.join("expected.txt"))?;
+    let diffs = report::diff_reports(&expected, &report.lines(), true);
+    for diff in &diffs {
+        info!(
+            "  {}: expected {}, replayed {}",
+            diff.field, diff.before, diff.after
+        );
+    }
+    if !diffs.is_empty() {
+        return Err(AppError::FixtureMismatch(diffs.len()));
+    }
+    Ok(report)
+}
+
+fn decode_hex<T: bitcoincore_rpc::bitcoin::consensus::Decodable>(
+    hex_str: &str,
+) -> Result<T, AppError> {
+    let bytes =
+        hex::decode(hex_str.trim()).map_err(|e| AppError::Decode(format!("invalid hex: {e}")))?;
+    Ok(deserialize(&bytes)?)
+}
+
+// The txid computed with the `bitcoin` crate, as analysis::verify_decoded_txid does
+fn compute_txid(hex_str: &str) -> Result<Txid, AppError> {
+    use bitcoin::hashes::Hash as _;
+
+    let bytes = hex::decode(hex_str).map_err(|e| AppError::Decode(format!("invalid hex: {e}")))?;
+    let tx: bitcoin::Transaction = bitcoin::consensus::deserialize(&bytes)
+        .map_err(|e| AppError::Decode(format!("raw transaction: {e}")))?;
+    Ok(Txid::from_byte_array(tx.compute_txid().to_byte_array()))
+}
+
+fn address_of(script: &Script, network: Network) -> String {
+    Address::from_script(script, network)
+        .map(|a| a.to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoincore_rpc::bitcoin::Amount;
+
+    fn example() -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/regtest-send")
+    }
+
+    #[test]
+    fn replays_the_example_fixture() {
+        let report = replay(&example()).unwrap();
+        assert_eq!(
+            report.txid.to_string(),
+            "8e405848a6fdf76d0129ef5b50c42c38a9463b8026df105e3981c7bb639a46bd"
+        );
+        assert_eq!(report.input_amount, Amount::from_sat(5_000_000_000));
+        assert_eq!(report.trader_amount, Amount::from_sat(2_000_000_000));
+        assert_eq!(report.change_amount, Amount::from_sat(2_999_998_590));
+        assert_eq!(report.fee, Amount::from_sat(1_410));
+        assert_eq!(report.block_height, 102);
+    }
+
+    #[test]
+    fn a_changed_expectation_fails_the_replay() {
+        let dir = std::env::temp_dir().join(format!("fixture-mismatch-{}", std::process::id()));
+        fs::create_dir_all(dir.join("prevouts")).unwrap();
+        for name in ["tx.hex", "header.hex", "fixture.json"] {
+            fs::copy(example().join(name), dir.join(name)).unwrap();
+        }
+        for entry in fs::read_dir(example().join("prevouts")).unwrap() {
+            let path = entry.unwrap().path();
+            fs::copy(&path, dir.join("prevouts").join(path.file_name().unwrap())).unwrap();
+        }
+        let expected = fs::read_to_string(example().join("expected.txt")).unwrap();
+        fs::write(
+            dir.join("expected.txt"),
+            expected.replace("0.00001410", "0.00001000"),
+        )
+        .unwrap();
+        let result = replay(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(result, Err(AppError::FixtureMismatch(1))));
+    }
+}
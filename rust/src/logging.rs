@@ -0,0 +1,61 @@
+// Central control of console verbosity
+// Informational output goes through `info!` and warnings through `warning!` so
+// flags like `--quiet` can silence them in one place; errors are always shown.
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+// Whether --explain narration is on; independent of the verbosity level
+static EXPLAIN: AtomicBool = AtomicBool::new(false);
+
+pub fn set_explain(on: bool) {
+    EXPLAIN.store(on, Ordering::Relaxed);
+}
+
+pub fn explaining() -> bool {
+    EXPLAIN.load(Ordering::Relaxed)
+}
+
+// Informational progress on stdout
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::logging::enabled($crate::logging::Level::Info) {
+            println!($($arg)*);
+        }
+    };
+}
+
+// Non-fatal problems on stderr
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        if $crate::logging::enabled($crate::logging::Level::Warn) {
+            eprintln!("Warning: {}", format_args!($($arg)*));
+        }
+    };
+}
+
+// Walkthrough narration for --explain: which RPC comes next and why
+macro_rules! explain {
+    ($($arg:tt)*) => {
+        if $crate::logging::explaining() {
+            println!("[explain] {}", format_args!($($arg)*));
+        }
+    };
+}
+
+pub(crate) use {explain, info, warning};
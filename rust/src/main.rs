@@ -1,124 +1,1225 @@
-// Enable unused code for development flexibility
-#![allow(unused)]
-use bitcoincore_rpc::bitcoin::Amount;
-use bitcoincore_rpc::{Auth, Client, RpcApi};
+mod amount;
+mod analysis;
+mod batch;
+mod bip21;
+mod config;
+mod error;
+mod fees;
+mod fixture;
+mod ledger;
+mod logging;
+mod mempool;
+mod mining;
+#[cfg(feature = "offline-sign")]
+mod offline;
+mod report;
+mod rescan;
+mod rpc;
+mod telemetry;
+mod wallet;
+mod watch;
+
+use bitcoincore_rpc::bitcoin::{Address, Amount, Network, OutPoint, Txid};
+use bitcoincore_rpc::json::FundRawTransactionOptions;
+use bitcoincore_rpc::{Client, RpcApi};
+use config::Config;
+use error::{is_method_not_found, is_retryable, AppError};
+use logging::{explain, info, warning};
+use report::write_report;
 use serde::Deserialize;
 use serde_json::json;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::Write;
+use std::path::Path;
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+use wallet::SendToAddressOptions;
 
-// Configuration for connecting to the Bitcoin Core node
+// Default connection to the Bitcoin Core node; see Config for overrides
 const NODE_URL: &str = "http://127.0.0.1:18443"; // Regtest RPC endpoint
 const NODE_USER: &str = "alice";
 const NODE_PASS: &str = "password";
 
+// Pause before a retried run, multiplied by the number of the failed attempt
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+// Amount moved from Miner to Trader in the default flow
+const TRANSFER_AMOUNT: Amount = Amount::from_int_btc(20);
+
+// Parameter layouts of the 'send' RPC across Bitcoin Core versions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SendParamShape {
+    // Core 0.21-23: every optional argument is passed positionally, as null when unset
+    Positional,
+    // Core 24+: unset trailing arguments are omitted so only known parameters are sent
+    Trimmed,
+}
+
+impl SendParamShape {
+    // Version numbers as reported by getnetworkinfo, e.g. 240001 for 24.0.1
+    fn for_version(version: usize) -> Self {
+        if version >= 240_000 {
+            SendParamShape::Trimmed
+        } else {
+            SendParamShape::Positional
+        }
+    }
+}
+
 // Custom RPC call for 'send' method, not directly exposed in the library
-fn send_transaction(rpc: &Client, address: &str) -> bitcoincore_rpc::Result<String> {
-    let params = [
-        json!([{address : 100 }]), // Target address for sending
-        json!(null),               // Confirmation target (default)
-        json!(null),               // Fee estimation mode
-        json!(null),               // Fee rate in satoshis per virtual byte
-        json!(null),               // Additional options (none)
-    ];
+fn send_transaction(
+    rpc: &Client,
+    address: &str,
+    amount: Amount,
+    shape: SendParamShape,
+) -> bitcoincore_rpc::Result<Txid> {
+    let outputs = json!([{ address: amount::to_rpc_value(amount) }]); // Target address for sending
+    let params = match shape {
+        SendParamShape::Positional => vec![
+            outputs,
+            json!(null), // Confirmation target (default)
+            json!(null), // Fee estimation mode
+            json!(null), // Fee rate in satoshis per virtual byte
+            json!({}),   // Additional options (none)
+        ],
+        SendParamShape::Trimmed => vec![outputs],
+    };
 
     #[derive(Deserialize)]
     struct TransactionResult {
         complete: bool,
-        txid: String,
+        txid: Txid,
     }
     let result = rpc.call::<TransactionResult>("send", &params)?;
-    assert!(result.complete, "Transaction failed to complete");
+    if !result.complete {
+        return Err(bitcoincore_rpc::Error::ReturnedError(
+            "send did not produce a complete transaction".to_string(),
+        ));
+    }
     Ok(result.txid)
 }
 
-// Empty address array for type safety
-static NO_ADDRESSES: [bitcoincore_rpc::bitcoin::Address<
-    bitcoincore_rpc::bitcoin::address::NetworkUnchecked,
->; 0] = [];
+// Which RPC ended up performing a payment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SendMethod {
+    /// The 'send' RPC (Core 0.21+)
+    Send,
+    /// The long-standing 'sendtoaddress' RPC
+    SendToAddress,
+    /// createrawtransaction, funded, signed and broadcast step by step
+    RawTransaction,
+}
 
-fn main() -> bitcoincore_rpc::Result<()> {
-    // Establish connection to Bitcoin Core node
-    let client = Client::new(
-        NODE_URL,
-        Auth::UserPass(NODE_USER.to_string(), NODE_PASS.to_string()),
+// Result of a payment, whichever RPC ended up performing it
+#[derive(Debug)]
+struct SendOutcome {
+    txid: Txid,
+    method: SendMethod,
+}
+
+// Whether the node knows an RPC. Asking 'help' executes nothing, and unknown
+// commands come back as text rather than an error.
+fn supports_rpc(rpc: &Client, method: &str) -> bitcoincore_rpc::Result<bool> {
+    let help = rpc.call::<String>("help", &[json!(method)])?;
+    Ok(!help.starts_with("help: unknown command"))
+}
+
+// Build, fund, sign and broadcast a payment without any of the send RPCs
+fn send_with_raw_transaction(
+    rpc: &Client,
+    address: &str,
+    amount: Amount,
+    options: Option<&FundRawTransactionOptions>,
+) -> Result<(Txid, Amount), AppError> {
+    let outputs = HashMap::from([(address.to_string(), amount)]);
+    let unfunded = rpc.create_raw_transaction_hex(&[], &outputs, None, None)?;
+    let funded = rpc.fund_raw_transaction(unfunded, options, None)?;
+    let signed = rpc.sign_raw_transaction_with_wallet(&funded.hex, None, None)?;
+    if !signed.complete {
+        return Err(AppError::Unsupported(
+            "the wallet could not sign the raw transaction".to_string(),
+        ));
+    }
+    Ok((rpc.send_raw_transaction(&signed.hex)?, funded.fee))
+}
+
+// Pay `amount` to `address` at exactly `sat_per_vb`, with no fee estimation,
+// and the change always placed after the payment. The same UTXOs therefore
+// give the same transaction shape and the same fee on every run.
+fn send_with_fixed_fee_rate(
+    rpc: &Client,
+    address: &Address,
+    amount: Amount,
+    sat_per_vb: u64,
+    subtract_fee: bool,
+) -> Result<(Txid, Amount), AppError> {
+    // The node refuses to relay anything paying less than its minimum relay fee
+    let relay_fee = rpc.get_network_info()?.relay_fee;
+    let fee_rate = Amount::from_sat(sat_per_vb * 1_000);
+    if fee_rate < relay_fee {
+        return Err(AppError::Usage(format!(
+            "--deterministic-fee {sat_per_vb} sat/vB is below the node's relay minimum of {} sat/vB",
+            relay_fee.to_sat() as f64 / 1_000.0
+        )));
+    }
+    let options = fixed_fee_rate_options(sat_per_vb, subtract_fee);
+    send_with_raw_transaction(rpc, &address.to_string(), amount, Some(&options))
+}
+
+// Funding options for --deterministic-fee, shared with the send preview
+fn fixed_fee_rate_options(sat_per_vb: u64, subtract_fee: bool) -> FundRawTransactionOptions {
+    FundRawTransactionOptions {
+        fee_rate: Some(Amount::from_sat(sat_per_vb * 1_000)),
+        change_position: Some(1),
+        subtract_fee_from_outputs: subtract_fee.then(|| vec![0]),
+        ..Default::default()
+    }
+}
+
+// What a payment would spend and pay, as funded by the wallet without sending
+#[derive(Debug)]
+struct SendPreview {
+    inputs: Vec<OutPoint>,
+    /// Value returned to the wallet, if funding added a change output
+    change: Option<Amount>,
+    fee: Amount,
+}
+
+// Fund a payment of `amount` to `recipient` the way the real send would, but
+// stop before signing. fundrawtransaction does not lock the coins it picks, so
+// the real send may still select differently; the fee is compared afterwards.
+fn simulate_send(
+    rpc: &Client,
+    recipient: &Address,
+    amount: Amount,
+    options: Option<&FundRawTransactionOptions>,
+) -> Result<SendPreview, AppError> {
+    let outputs = HashMap::from([(recipient.to_string(), amount)]);
+    let unfunded = rpc.create_raw_transaction_hex(&[], &outputs, None, None)?;
+    let funded = rpc.fund_raw_transaction(unfunded, options, None)?;
+    let tx: bitcoincore_rpc::bitcoin::Transaction =
+        bitcoincore_rpc::bitcoin::consensus::deserialize(&funded.hex)?;
+    // A change position of -1 means the wallet added no change output
+    let change = usize::try_from(funded.change_position)
+        .ok()
+        .and_then(|position| tx.output.get(position))
+        .map(|output| output.value);
+    Ok(SendPreview {
+        inputs: tx.input.iter().map(|input| input.previous_output).collect(),
+        change,
+        fee: funded.fee,
+    })
+}
+
+// Pay `amount` to `address` through 'send' when the node has it, falling back
+// to 'sendtoaddress' and then to a hand-built raw transaction
+fn pay(
+    rpc: &Client,
+    address: &Address,
+    amount: Amount,
+    shape: SendParamShape,
+) -> Result<SendOutcome, AppError> {
+    let recipient = address.to_string();
+    if supports_rpc(rpc, "send")? {
+        let txid = send_transaction(rpc, &recipient, amount, shape)?;
+        return Ok(SendOutcome {
+            txid,
+            method: SendMethod::Send,
+        });
+    }
+    if supports_rpc(rpc, "sendtoaddress")? {
+        info!("Node lacks send, falling back to sendtoaddress");
+        let txid = SendToAddressOptions::new().send(rpc, address, amount)?;
+        return Ok(SendOutcome {
+            txid,
+            method: SendMethod::SendToAddress,
+        });
+    }
+    for method in [
+        "createrawtransaction",
+        "fundrawtransaction",
+        "signrawtransactionwithwallet",
+    ] {
+        if !supports_rpc(rpc, method)? {
+            return Err(AppError::Unsupported(format!(
+                "the node has no send, sendtoaddress or {method} RPC"
+            )));
+        }
+    }
+    info!("Node lacks send and sendtoaddress, building a raw transaction");
+    Ok(SendOutcome {
+        txid: send_with_raw_transaction(rpc, &recipient, amount, None)?.0,
+        method: SendMethod::RawTransaction,
+    })
+}
+
+// Print what each recipient actually received next to what was requested;
+// the two differ for recipients that had the fee deducted
+fn print_recipient_amounts(
+    rpc: &Client,
+    txid: &Txid,
+    requested: &[(String, Amount)],
+    subtract_fee_from: &[usize],
+) -> Result<(), AppError> {
+    let hex = rpc.get_raw_transaction_hex(txid, None)?;
+    let decoded = rpc.decode_raw_transaction(hex.as_str(), None)?;
+    info!("Recipient amounts:");
+    for (index, (address, amount)) in requested.iter().enumerate() {
+        let actual = decoded
+            .vout
+            .iter()
+            .find(|o| analysis::script_address(&o.script_pub_key) == *address)
+            .map(|o| o.value)
+            .unwrap_or(Amount::ZERO);
+        let note = if subtract_fee_from.contains(&index) {
+            " (fee deducted)"
+        } else {
+            ""
+        };
+        info!(
+            "  [{index}] {address}: requested {} BTC, received {} BTC{note}",
+            amount::to_btc_string(*amount),
+            amount::to_btc_string(actual)
+        );
+    }
+    Ok(())
+}
+
+// Typed parameters for the 'sendall' RPC (Bitcoin Core 24+)
+#[derive(Debug, Clone)]
+struct SendAllRequest {
+    recipients: Vec<String>,
+    inputs: Vec<OutPoint>,
+}
+
+impl SendAllRequest {
+    // Positional params: recipients, conf_target, estimate_mode, fee_rate, options
+    fn params(&self) -> Vec<serde_json::Value> {
+        let mut params = vec![
+            json!(self.recipients),
+            json!(null),
+            json!(null),
+            json!(null),
+        ];
+        // Without explicit inputs, sendall sweeps every confirmed UTXO in the wallet
+        if !self.inputs.is_empty() {
+            let inputs: Vec<_> = self
+                .inputs
+                .iter()
+                .map(|o| json!({ "txid": o.txid.to_string(), "vout": o.vout }))
+                .collect();
+            params.push(json!({ "inputs": inputs }));
+        }
+        params
+    }
+}
+
+// Result of a sweep, whichever RPC ended up performing it
+#[derive(Debug)]
+struct SweepOutcome {
+    txid: Txid,
+    swept: Amount,
+    fee: Amount,
+}
+
+// Custom RPC call for 'sendall', not exposed by the library
+fn send_all(rpc: &Client, request: &SendAllRequest) -> bitcoincore_rpc::Result<Txid> {
+    #[derive(Deserialize)]
+    struct SendAllResult {
+        complete: bool,
+        txid: Option<Txid>,
+    }
+    let result = rpc.call::<SendAllResult>("sendall", &request.params())?;
+    match result.txid {
+        Some(txid) if result.complete => Ok(txid),
+        _ => Err(bitcoincore_rpc::Error::ReturnedError(
+            "sendall did not produce a complete transaction".to_string(),
+        )),
+    }
+}
+
+// Fallback for nodes without 'sendall': pay the inputs' total to a single
+// recipient and let the fee come out of that amount. Coin selection is left to
+// the wallet, so specific inputs only determine how much is swept.
+fn sweep_with_send_to_address(rpc: &Client, request: &SendAllRequest) -> Result<Txid, AppError> {
+    let [recipient] = request.recipients.as_slice() else {
+        return Err(AppError::Unsupported(
+            "sweeping to several recipients requires the sendall RPC".to_string(),
+        ));
+    };
+    let address = Address::from_str(recipient)
+        .map_err(|e| AppError::Usage(format!("invalid recipient '{recipient}': {e}")))?
+        .assume_checked();
+
+    let amount = if request.inputs.is_empty() {
+        rpc.get_balance(None, None)?
+    } else {
+        let mut total = Amount::ZERO;
+        for input in &request.inputs {
+            let utxo = rpc
+                .get_tx_out(&input.txid, input.vout, Some(false))?
+                .ok_or_else(|| AppError::Usage(format!("input {input} is not unspent")))?;
+            total += utxo.value;
+        }
+        total
+    };
+
+    Ok(SendToAddressOptions::new()
+        .subtract_fee(true)
+        .send(rpc, &address, amount)?)
+}
+
+// Report whether the output paying the Trader is visible to gettxout yet
+fn print_trader_output(
+    rpc: &Client,
+    txid: &Txid,
+    trader_addr: &Address,
+    include_mempool: bool,
+) -> Result<(), AppError> {
+    explain!(
+        "Calling gettxout to check the Trader output is unspent, include_mempool={include_mempool}"
+    );
+    match wallet::locate_output(rpc, txid, trader_addr, include_mempool)? {
+        Some((vout, location)) => info!("Trader output {txid}:{vout}: {location}"),
+        None => info!("Transaction {txid} pays nothing to the Trader address {trader_addr}"),
+    }
+    Ok(())
+}
+
+// Sweep the requested inputs, preferring 'sendall' when the node provides it
+fn sweep(rpc: &Client, request: &SendAllRequest) -> Result<SweepOutcome, AppError> {
+    let txid = match send_all(rpc, request) {
+        Ok(txid) => txid,
+        Err(e) if is_method_not_found(&e) => {
+            info!("Node lacks sendall, falling back to sendtoaddress with subtract-fee");
+            sweep_with_send_to_address(rpc, request)?
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    // Everything swept either reached an output or was paid as fee
+    let wallet_tx = rpc.get_transaction(&txid, None)?;
+    let fee = wallet_tx
+        .fee
+        .map(|f| f.abs().to_unsigned())
+        .transpose()?
+        .unwrap_or(Amount::ZERO);
+    let outputs: Amount = wallet_tx
+        .transaction()?
+        .output
+        .iter()
+        .map(|o| o.value)
+        .sum();
+    Ok(SweepOutcome {
+        txid,
+        swept: outputs + fee,
+        fee,
+    })
+}
+
+// Self-send every spendable Miner UTXO to `address`, then check the wallet's
+// only non-coinbase UTXO is that single output. The confirming block pays
+// `mine_to`, and it can mature an older Miner coinbase; those stay spendable
+// next to the consolidated output, and the output says so.
+fn consolidate(rpc: &Client, address: &Address, mine_to: &Address) -> Result<(), AppError> {
+    explain!("Calling listunspent on the Miner wallet to gather every spendable UTXO");
+    let utxos = rpc.list_unspent(Some(1), None, None, None, None)?;
+    let swept: HashSet<OutPoint> = utxos
+        .iter()
+        .map(|u| OutPoint::new(u.txid, u.vout))
+        .collect();
+    info!("Consolidating {} UTXO(s) into {address}", utxos.len());
+
+    explain!("Calling sendall with every listed UTXO as an explicit input");
+    let outcome = sweep(
+        rpc,
+        &SendAllRequest {
+            recipients: vec![address.to_string()],
+            inputs: swept.iter().copied().collect(),
+        },
     )?;
+    let vsize = rpc.get_mempool_entry(&outcome.txid)?.vsize;
+    rpc.generate_to_address(1, mine_to)?;
+
+    // Anything still spendable is the consolidated output, a coinbase that
+    // matured meanwhile, or a swept UTXO the sweep failed to spend
+    let mut remaining = Vec::new();
+    let mut matured = 0;
+    for utxo in rpc.list_unspent(Some(1), None, None, None, None)? {
+        let outpoint = OutPoint::new(utxo.txid, utxo.vout);
+        let coinbase = utxo.txid != outcome.txid
+            && !swept.contains(&outpoint)
+            && rpc
+                .get_transaction(&utxo.txid, None)?
+                .transaction()?
+                .is_coinbase();
+        if coinbase {
+            matured += 1;
+        } else {
+            remaining.push(utxo);
+        }
+    }
+    match remaining.as_slice() {
+        [utxo] if utxo.txid == outcome.txid => {
+            info!(
+                "Consolidated {} input(s) into {}:{} worth {} BTC",
+                utxos.len(),
+                utxo.txid,
+                utxo.vout,
+                amount::to_btc_string(utxo.amount)
+            );
+        }
+        _ => return Err(AppError::ConsolidationIncomplete(remaining.len())),
+    }
+    if matured > 0 {
+        warning!(
+            "the Miner holds {matured} more UTXO(s): coinbases that matured during consolidation, so only the non-coinbase UTXOs were checked"
+        );
+    }
+    info!(
+        "Consolidation fee: {} BTC ({:.2} sat/vB over {vsize} vB)",
+        amount::to_btc_string(outcome.fee),
+        fees::fee_rate_sat_vb(outcome.fee, vsize as u32)
+    );
+    Ok(())
+}
+
+// Mine one block at a time until the wallet reports a positive balance,
+// giving up after `max_blocks` so a wallet that is never credited cannot spin forever
+fn mine_until_funded(
+    rpc: &Client,
+    address: &Address,
+    max_blocks: u64,
+) -> Result<(u64, Amount), AppError> {
+    // Warn once when only the last tenth of the allowance remains
+    let warn_at = mining::stall_warning_at(max_blocks);
+    let mut balance = rpc.get_balance(None, None)?;
+    let mut blocks = 0;
+    while balance == Amount::ZERO {
+        if blocks == max_blocks {
+            return Err(AppError::MiningStalled { blocks });
+        }
+        rpc.generate_to_address(1, address)?;
+        blocks += 1;
+        if blocks == warn_at && blocks < max_blocks {
+            warning!("mined {blocks} of at most {max_blocks} blocks without a spendable balance");
+        }
+        balance = rpc.get_balance(None, None)?;
+    }
+    Ok((blocks, balance))
+}
+
+// Connect to the node with the configured endpoint and credentials
+fn node_client(config: &Config) -> bitcoincore_rpc::Result<Client> {
+    rpc::connect(
+        &config.rpc_url,
+        &config.rpc_user,
+        &config.rpc_password,
+        rpc::DEFAULT_TIMEOUT,
+    )
+}
+
+// Connect to the RPC endpoint of a single named wallet
+fn wallet_client(config: &Config, name: &str) -> bitcoincore_rpc::Result<Client> {
+    wallet_client_with_timeout(config, name, rpc::DEFAULT_TIMEOUT)
+}
+
+fn wallet_client_with_timeout(
+    config: &Config,
+    name: &str,
+    timeout: Duration,
+) -> bitcoincore_rpc::Result<Client> {
+    rpc::connect(
+        &format!("{}/wallet/{name}", config.rpc_url),
+        &config.rpc_user,
+        &config.rpc_password,
+        timeout,
+    )
+}
+
+// Ask a yes/no question on the terminal; anything but "y"/"yes" declines
+fn confirm(prompt: &str) -> bool {
+    eprint!("{prompt} ");
+    let _ = std::io::stderr().flush();
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+fn main() -> ExitCode {
+    telemetry::start_run();
+    let result = Config::from_args(std::env::args().skip(1))
+        .and_then(|config| run_with_retries(&config, config.attempts));
+    telemetry::finish_run(result.is_ok());
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// Run the whole flow up to `attempts` times. Only errors a restarting or
+// still-loading node can cause are retried; the wallets are unloaded first so
+// the next attempt loads them from scratch.
+fn run_with_retries(config: &Config, attempts: u32) -> Result<(), AppError> {
+    let mut attempt = 1;
+    loop {
+        // Each attempt has the whole --rpc-budget to itself
+        rpc::reset_call_count();
+        let result = run(config);
+        if attempts > 1 {
+            match &result {
+                Ok(()) => info!("Attempt {attempt}/{attempts} succeeded"),
+                Err(e) => warning!("Attempt {attempt}/{attempts} failed: {e}"),
+            }
+        }
+        match result {
+            Err(e) if attempt < attempts && is_retryable(&e) => {
+                unload_wallets(config);
+                thread::sleep(RETRY_DELAY * attempt);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+// Best effort: a wallet that is not loaded, or a node that is down, is fine
+fn unload_wallets(config: &Config) {
+    let Ok(client) = node_client(config) else {
+        return;
+    };
+    for wallet in [&config.miner_wallet, &config.trader_wallet] {
+        if client.unload_wallet(Some(wallet)).is_ok() {
+            info!("Unloaded wallet {wallet} before retrying");
+        }
+    }
+}
+
+fn run(config: &Config) -> Result<(), AppError> {
+    if config.quiet {
+        logging::set_level(logging::Level::Error);
+    }
+    logging::set_explain(config.explain);
+    analysis::set_halving_interval(config.halving_interval);
+    if let Some(dir) = &config.from_fixture {
+        let replayed = fixture::replay(dir)?;
+        info!(
+            "Replayed {} from {}: report matches the fixture",
+            replayed.txid,
+            dir.display()
+        );
+        return Ok(());
+    }
+    #[cfg(feature = "offline-sign")]
+    if let Some(spec) = &config.offline_sign {
+        // Signing needs only the spec; the node is contacted just to broadcast
+        let spend = offline::read_spend(spec)?;
+        let tx = offline::build_signed_tx(&spend.utxos, &spend.keys, &spend.outputs)?;
+        let hex = bitcoin::consensus::encode::serialize_hex(&tx);
+        let path = report::write_raw_tx(Path::new(".."), &hex)?;
+        info!(
+            "Signed {} offline, spending {} UTXO(s); wrote it to {}",
+            tx.compute_txid(),
+            spend.utxos.len(),
+            path.display()
+        );
+        if config.offline_broadcast {
+            let txid = offline::broadcast(&node_client(config)?, &tx)?;
+            info!("Broadcast offline-signed transaction {txid}");
+        }
+        return Ok(());
+    }
+    if let Some(path) = &config.export_env {
+        config.to_env_file(path)?;
+        info!("Wrote settings to {}", path.display());
+        return Ok(());
+    }
+
+    // Establish connection to Bitcoin Core node
+    let phase = telemetry::span("setup");
+    explain!(
+        "Opening a JSON-RPC connection to the regtest node at {}",
+        config.rpc_url
+    );
+    let client = node_client(config)?;
 
     // Retrieve and display blockchain information
+    explain!("Calling getblockchaininfo to learn which chain the node is on and its height");
     let chain_info = client.get_blockchain_info()?;
-    println!("Chain Info: {chain_info:#?}");
+    info!("Chain Info: {chain_info:#?}");
+
+    // Demonstrations that spend the Miner's coins freely only make sense on regtest
+    if chain_info.chain != Network::Regtest {
+        if config.warmup_feeest {
+            return Err(AppError::Usage(format!(
+                "--warmup-feeest mines blocks of self-payments and is only allowed on regtest, not {}",
+                chain_info.chain
+            )));
+        }
+        if config.demo_mempool_pressure.is_some() {
+            return Err(AppError::Usage(format!(
+                "--demo-mempool-pressure broadcasts filler transactions and is only allowed on regtest, not {}",
+                chain_info.chain
+            )));
+        }
+    }
 
-    // Initialize or load wallets 'Miner' and 'Trader'
-    for wallet in ["Miner", "Trader"] {
+    // Several RPCs changed shape across releases; honour an explicit hint
+    let node_version = match config.core_version {
+        Some(version) => version,
+        None => client.version()?,
+    };
+
+    // Initialize or load the Miner and Trader wallets
+    let (miner_wallet, trader_wallet) =
+        (config.miner_wallet.as_str(), config.trader_wallet.as_str());
+    explain!("Calling createwallet (or loadwallet) so both wallets exist and are loaded");
+    for wallet in [miner_wallet, trader_wallet] {
         match client.create_wallet(wallet, None, None, None, None) {
-            Ok(_) => println!("Created wallet: {wallet}"),
+            Ok(_) => info!("Created wallet: {wallet}"),
+            // The wallet exists on disk; it may still need loading
             Err(e) if e.to_string().contains("already exists") => {
-                println!("Wallet {wallet} already loaded")
+                match client.load_wallet(wallet) {
+                    Ok(_) => info!("Loaded existing wallet: {wallet}"),
+                    Err(e) if e.to_string().contains("already loaded") => {
+                        info!("Wallet {wallet} already loaded")
+                    }
+                    Err(e) => return Err(e.into()),
+                }
             }
-            Err(e) => return Err(e),
+            Err(e) => return Err(e.into()),
         }
     }
 
     // Connect to wallet-specific RPC endpoints
-    let miner_client = Client::new(
-        &format!("{}/wallet/{}", NODE_URL, "Miner"),
-        Auth::UserPass(NODE_USER.to_string(), NODE_PASS.to_string()),
-    )?;
-    let trader_client = Client::new(
-        &format!("{}/wallet/{}", NODE_URL, "Trader"),
-        Auth::UserPass(NODE_USER.to_string(), NODE_PASS.to_string()),
-    )?;
+    let miner_client = wallet_client(config, miner_wallet)?;
+    let trader_client = wallet_client(config, trader_wallet)?;
+
+    if config.abort_rescan {
+        // Stop a rescan started by another run, then exit without sending
+        for (name, wallet) in [
+            (miner_wallet, &miner_client),
+            (trader_wallet, &trader_client),
+        ] {
+            match rescan::abort_rescan(wallet)? {
+                Some(progress) => {
+                    info!("Aborted {name} rescan at {:.0}% progress", progress * 100.0)
+                }
+                None => info!("No rescan in progress for {name}"),
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(wallet) = &config.watch {
+        // Monitor a wallet until Ctrl-C instead of sending anything
+        let client = if wallet == miner_wallet {
+            miner_client
+        } else if wallet == trader_wallet {
+            trader_client
+        } else {
+            wallet_client(config, wallet)?
+        };
+        watch::watch(&client, Duration::from_secs(config.watch_interval))?;
+        return Ok(());
+    }
+
+    if let Some(since) = config.since_height {
+        // Export the Miner's activity block by block instead of sending
+        let ledger = ledger::build_ledger(&miner_client, since)?;
+        let path = Path::new("..").join("ledger.csv");
+        ledger::write_ledger_csv(&ledger, &mut File::create(&path)?)?;
+        info!(
+            "Wrote {} ledger row(s) since height {since} to {}",
+            ledger.rows.len(),
+            path.display()
+        );
+        if ledger.reorged > 0 {
+            warning!(
+                "left out {} entries from reorganised blocks",
+                ledger.reorged
+            );
+        }
+        return Ok(());
+    }
+
+    if !config.analyze_txids.is_empty() {
+        // Analyse the given transactions instead of running the send flow
+        let outcome = batch::analyze_many(
+            config,
+            &config.analyze_txids,
+            node_version,
+            chain_info.chain,
+        );
+        let formatter = config.format.formatter();
+        for report in &outcome.reports {
+            match config.format.file_name() {
+                Some(name) => {
+                    let path = Path::new("..").join(format!("{}-{name}", report.txid));
+                    formatter.write(report, &mut File::create(&path)?)?;
+                    info!("Saved {} report to {}", report.txid, path.display());
+                }
+                None => formatter.write(report, &mut std::io::stdout().lock())?,
+            }
+        }
+        for (txid, e) in &outcome.errors {
+            warning!("could not analyse {txid}: {e}");
+        }
+        if !outcome.errors.is_empty() {
+            return Err(AppError::BatchFailed {
+                failed: outcome.errors.len(),
+                total: config.analyze_txids.len(),
+            });
+        }
+        return Ok(());
+    }
+
+    if let Some(start_height) = config.rescan_from {
+        // rescanblockchain only answers once the whole range is scanned
+        match rescan::rescan(
+            wallet_client_with_timeout(config, miner_wallet, rpc::LONG_CALL_TIMEOUT)?,
+            &miner_client,
+            start_height,
+        )? {
+            rescan::RescanOutcome::Completed {
+                start_height,
+                stop_height,
+            } => info!(
+                "Rescanned Miner from height {start_height} to {}",
+                stop_height.map_or("tip".to_string(), |h| h.to_string())
+            ),
+            rescan::RescanOutcome::Aborted { progress } => {
+                warning!(
+                    "Miner rescan was aborted at {:.0}% progress",
+                    progress * 100.0
+                )
+            }
+        }
+    }
+
+    phase.end();
 
     // Generate funds in Miner wallet by mining blocks
+    let mut phase = telemetry::span("mining");
     // Obtain a new address for mining rewards
-    let miner_addr = miner_client
-        .get_new_address(Some("Mining Reward"), None)?
-        .assume_checked();
-    println!("Miner address for rewards: {miner_addr}");
+    explain!("Calling getnewaddress on the Miner wallet for an address to mine rewards to");
+    let miner_addr = match &config.mine_to {
+        Some(address) => {
+            let address = address
+                .clone()
+                .require_network(chain_info.chain)
+                .map_err(|e| {
+                    AppError::Usage(format!(
+                        "--mine-to address is not for {}: {e}",
+                        chain_info.chain
+                    ))
+                })?;
+            let is_mine = miner_client
+                .get_address_info(&address)?
+                .is_mine
+                .unwrap_or(false);
+            if !is_mine {
+                warning!("{address} is not a Miner wallet address; the Miner will have no spendable funds to send");
+            }
+            address
+        }
+        None => miner_client
+            .get_new_address(Some("Mining Reward"), config.address_type)?
+            .assume_checked(),
+    };
+    info!("Miner address for rewards: {miner_addr}");
 
     // Mine blocks until Miner has spendable funds
     // Note: Coinbase outputs need 100 confirmations to mature
-    let mut balance = miner_client.get_balance(None, None)?.to_btc();
-    let mut blocks = 0;
-    while balance <= 0.0 {
-        miner_client.generate_to_address(1, &miner_addr)?;
-        blocks += 1;
-        balance = miner_client.get_balance(None, None)?.to_btc();
-    }
-    println!("Mined {blocks} blocks to achieve balance: {balance} BTC");
+    explain!("Calling generatetoaddress; a coinbase is spendable only after 100 more blocks, so getbalance stays zero until then");
+    let (blocks, balance) = if config.check_maturity {
+        let boundary = mining::assert_maturity_boundary(&miner_client, &miner_addr)?;
+        info!(
+            "Coinbase from block {} was immature at height {} and spendable at height {}",
+            boundary.coinbase_height, boundary.immature_height, boundary.mature_height
+        );
+        (
+            mining::COINBASE_MATURITY + 1,
+            miner_client.get_balance(None, None)?,
+        )
+    } else {
+        mine_until_funded(&miner_client, &miner_addr, config.max_blocks)?
+    };
+    info!(
+        "Mined {blocks} blocks to achieve balance: {} BTC",
+        amount::to_btc_string(balance)
+    );
+    phase.attr("blocks", blocks);
+    phase.end();
 
     // Generate a receiving address for Trader wallet
-    let trader_addr = trader_client
-        .get_new_address(Some("Payment"), None)?
-        .assume_checked();
-    println!("Trader payment address: {trader_addr}");
-
-    // Transfer 20 BTC from Miner to Trader
-    let tx_id = miner_client.send_to_address(
-        &trader_addr,
-        Amount::from_btc(20.0)?,
-        None,
-        None,
-        None,
-        None,
-        None,
-        None,
-    )?;
-    println!("Transferred 20 BTC to Trader. TxID: {tx_id}");
+    explain!("Calling getnewaddress on the Trader wallet for the payment destination");
+    let trader_addr = match &config.pay {
+        // A BIP21 URI names the destination itself, which must be on this chain
+        Some(uri) => uri
+            .address
+            .clone()
+            .require_network(chain_info.chain)
+            .map_err(|e| {
+                AppError::Usage(format!(
+                    "--pay address is not for {}: {e}",
+                    chain_info.chain
+                ))
+            })?,
+        None => trader_client
+            .get_new_address(Some("Payment"), config.address_type)?
+            .assume_checked(),
+    };
+    info!("Trader payment address: {trader_addr}");
+    let transfer_amount = config
+        .pay
+        .as_ref()
+        .and_then(|uri| uri.amount)
+        .unwrap_or(TRANSFER_AMOUNT);
+    let transfer_btc = amount::to_btc_string(transfer_amount);
+
+    // Restore a captured mempool so the send below meets the same fee market
+    if let Some(path) = &config.load_mempool {
+        explain!("Calling sendrawtransaction for every transaction in the dump, since a running node only reads mempool.dat at startup");
+        let load = mempool::load_mempool(&miner_client, path)?;
+        info!(
+            "Loaded {} transaction(s) from {} into the mempool, {} rejected",
+            load.loaded,
+            path.display(),
+            load.rejected
+        );
+    }
+
+    // Crowd the mempool with cheap fillers so the send below competes for space
+    if let Some(kvb) = config.demo_mempool_pressure {
+        mempool::print_snapshot(
+            "Mempool before pressure:",
+            &mempool::snapshot(&miner_client)?,
+        );
+        let stats = mempool::fill_mempool(&miner_client, &miner_addr, kvb * 1_000)?;
+        info!(
+            "Fillers: {} accepted, {} rejected, {} evicted after acceptance",
+            stats.accepted, stats.rejected, stats.evicted
+        );
+        mempool::print_snapshot(
+            "Mempool under pressure:",
+            &mempool::snapshot(&miner_client)?,
+        );
+    }
+
+    // Fresh regtest has no fee history, so estimatesmartfee answers nothing
+    if config.warmup_feeest {
+        fees::print_estimates(&miner_client, "Fee estimates before warm-up:")?;
+        explain!("Sending self-payments at varied fee rates and mining them, so the estimator sees transactions move from mempool to block");
+        let sent = fees::warm_up_estimator(&miner_client, &miner_addr)?;
+        info!("Warm-up confirmed {sent} self-payments at varied fee rates");
+        fees::print_estimates(&miner_client, "Fee estimates after warm-up:")?;
+    }
+
+    // Show both wallets side by side before funds move
+    let wallets = [
+        (miner_wallet, &miner_client),
+        (trader_wallet, &trader_client),
+    ];
+    let display = config.display_format.formatter();
+    wallet::print_balances(
+        "Balances before transfer:",
+        &wallet::all_balances(&wallets)?,
+        display,
+    );
+
+    // All Miner funds move to one new Miner address instead of the Trader
+    let consolidation_addr = if config.consolidate {
+        Some(
+            miner_client
+                .get_new_address(Some("Consolidated"), config.address_type)?
+                .assume_checked(),
+        )
+    } else {
+        None
+    };
+
+    // A watch-only Miner cannot sign, so hand over an unsigned PSBT instead
+    if !miner_client.get_wallet_info()?.private_keys_enabled {
+        if config.sendall || config.consolidate {
+            return Err(AppError::Unsupported(
+                "--sendall and --consolidate need a wallet with private keys".to_string(),
+            ));
+        }
+        let outputs: HashMap<String, Amount> = if config.recipients.is_empty() {
+            HashMap::from([(trader_addr.to_string(), transfer_amount)])
+        } else {
+            config.recipients.iter().cloned().collect()
+        };
+        let funded = miner_client.wallet_create_funded_psbt(&[], &outputs, None, None, None)?;
+        let path = report::write_psbt(Path::new(".."), &funded.psbt)?;
+        info!(
+            "Miner wallet has private keys disabled; wrote an unsigned PSBT (fee {} BTC) to {}",
+            amount::to_btc_string(funded.fee),
+            path.display()
+        );
+        info!("Sign it with the external signer holding the keys, then broadcast it with sendrawtransaction");
+        return Ok(());
+    }
+
+    // Preview the payment before anything is signed or broadcast
+    let preview = if config.simulate || config.dry_run {
+        explain!("Calling fundrawtransaction to preview the inputs, change and fee of the send");
+        let subtract_fee = config.subtract_fee_from.is_some();
+        let options = match config.deterministic_fee {
+            Some(rate) => fixed_fee_rate_options(rate, subtract_fee),
+            None => FundRawTransactionOptions {
+                subtract_fee_from_outputs: subtract_fee.then(|| vec![0]),
+                ..Default::default()
+            },
+        };
+        let preview = simulate_send(&miner_client, &trader_addr, transfer_amount, Some(&options))?;
+        info!("Send preview: {transfer_btc} BTC to {trader_addr}");
+        for input in &preview.inputs {
+            info!("  input {input}");
+        }
+        match preview.change {
+            Some(change) => info!("  change {} BTC", amount::to_btc_string(change)),
+            None => info!("  no change output"),
+        }
+        info!("  fee {} BTC", amount::to_btc_string(preview.fee));
+        if config.dry_run {
+            info!("Dry run: nothing was sent");
+            return Ok(());
+        }
+        Some(preview)
+    } else {
+        None
+    };
+
+    // Off regtest the coins are real, so ask before anything is sent
+    if chain_info.chain != Network::Regtest && !config.yes {
+        let destination = if let Some(address) = &consolidation_addr {
+            format!("every Miner UTXO to {address}")
+        } else if config.sendall {
+            let recipients = if config.sendall_recipients.is_empty() {
+                trader_addr.to_string()
+            } else {
+                config.sendall_recipients.join(", ")
+            };
+            format!("the selected inputs to {recipients}")
+        } else if !config.recipients.is_empty() {
+            let payments: Vec<_> = config
+                .recipients
+                .iter()
+                .map(|(address, value)| {
+                    format!("{} BTC to {address}", amount::to_btc_string(*value))
+                })
+                .collect();
+            payments.join(", ")
+        } else {
+            format!("{transfer_btc} BTC to {trader_addr}")
+        };
+        let prompt = format!(
+            "About to send {destination} on {}, proceed? [y/N]",
+            chain_info.chain
+        );
+        if !confirm(&prompt) {
+            return Err(AppError::Cancelled(
+                "send declined at the confirmation prompt".to_string(),
+            ));
+        }
+    }
 
+    let mut phase = telemetry::span("send");
+    // Catch the same recipient being paid twice, e.g. a repeated --recipient
+    let recipients: Vec<String> = if let Some(address) = &consolidation_addr {
+        vec![address.to_string()]
+    } else if config.sendall && !config.sendall_recipients.is_empty() {
+        config.sendall_recipients.clone()
+    } else if !config.recipients.is_empty() {
+        config
+            .recipients
+            .iter()
+            .map(|(address, _)| address.clone())
+            .collect()
+    } else {
+        vec![trader_addr.to_string()]
+    };
+    wallet::check_recipients(&recipients, config.allow_reuse)?;
+    if let Some(address) = &consolidation_addr {
+        let result = consolidate(&miner_client, address, &miner_addr);
+        if result.is_ok() {
+            wallet::record_recipients(&recipients);
+        }
+        phase.end();
+        return result;
+    }
+    explain!("Sending from the Miner wallet: the wallet selects coins, adds change and signs before broadcasting");
+    let tx_id = if config.sendall {
+        // Sweep the selected inputs (or the whole wallet) to the recipients
+        let recipients = if config.sendall_recipients.is_empty() {
+            vec![trader_addr.to_string()]
+        } else {
+            config.sendall_recipients.clone()
+        };
+        let request = SendAllRequest {
+            recipients,
+            inputs: config.sendall_inputs.clone(),
+        };
+        let outcome = sweep(&miner_client, &request)?;
+        info!(
+            "Swept {} BTC to {} recipient(s), fee {} BTC. TxID: {}",
+            amount::to_btc_string(outcome.swept),
+            request.recipients.len(),
+            amount::to_btc_string(outcome.fee),
+            outcome.txid
+        );
+        outcome.txid
+    } else if config.send_rpc {
+        // Transfer 20 BTC through the 'send' RPC, shaped for the node's version
+        let shape = SendParamShape::for_version(node_version);
+        let outcome = pay(&miner_client, &trader_addr, transfer_amount, shape)?;
+        info!(
+            "Transferred {transfer_btc} BTC to Trader via {:?} ({shape:?} params, Core {node_version}). TxID: {}",
+            outcome.method, outcome.txid
+        );
+        outcome.txid
+    } else if !config.recipients.is_empty() {
+        // Pay every recipient in one transaction
+        let subtract_from = config
+            .subtract_fee_from
+            .as_ref()
+            .map(|s| s.indices(config.recipients.len()))
+            .unwrap_or_default();
+        let tx_id = wallet::send_many(&miner_client, &config.recipients, &subtract_from)?;
+        info!("Paid {} recipients. TxID: {tx_id}", config.recipients.len());
+        print_recipient_amounts(&miner_client, &tx_id, &config.recipients, &subtract_from)?;
+        tx_id
+    } else if let Some(rate) = config.deterministic_fee {
+        // Fund at a fixed rate so the fee field is reproducible across runs
+        let subtract_fee = config.subtract_fee_from.is_some();
+        let (tx_id, fee) = send_with_fixed_fee_rate(
+            &miner_client,
+            &trader_addr,
+            transfer_amount,
+            rate,
+            subtract_fee,
+        )?;
+        info!("Transferred {transfer_btc} BTC to Trader. TxID: {tx_id}");
+        info!(
+            "Fee is deterministic: {} BTC at a fixed {rate} sat/vB, no estimation used",
+            amount::to_btc_string(fee)
+        );
+        tx_id
+    } else {
+        // Transfer 20 BTC from Miner to Trader, optionally out of the 20 BTC itself
+        let subtract_fee = config.subtract_fee_from.is_some();
+        let mut options = SendToAddressOptions::new();
+        if subtract_fee {
+            options = options.subtract_fee(true);
+        }
+        // The URI's message and label become the wallet's comment and comment_to
+        if let Some(uri) = &config.pay {
+            if let Some(message) = &uri.message {
+                options = options.comment(message);
+            }
+            if let Some(label) = &uri.label {
+                options = options.comment_to(label);
+            }
+        }
+        let tx_id = options.send(&miner_client, &trader_addr, transfer_amount)?;
+        info!("Transferred {transfer_btc} BTC to Trader. TxID: {tx_id}");
+        if subtract_fee {
+            let requested = [(trader_addr.to_string(), transfer_amount)];
+            print_recipient_amounts(&miner_client, &tx_id, &requested, &[0])?;
+        }
+        tx_id
+    };
+    wallet::record_recipients(&recipients);
+    phase.attr("txid", tx_id);
+    phase.end();
+
+    explain!("Calling getmempoolentry to see the unconfirmed transaction waiting in the mempool");
     // Verify transaction in mempool
     let mempool_data = miner_client.get_mempool_entry(&tx_id)?;
-    println!("Mempool data for TxID {tx_id}: {mempool_data:#?}");
+    info!("Mempool data for TxID {tx_id}: {mempool_data:#?}");
+    if config.demo_mempool_pressure.is_some() {
+        let pressure = mempool::snapshot(&miner_client)?;
+        info!(
+            "Demo transaction accepted under pressure, paying {} BTC against a mempoolminfee of {} BTC/kvB",
+            amount::to_btc_string(mempool_data.fees.base),
+            amount::to_btc_string(pressure.min_fee)
+        );
+    }
+    print_trader_output(&miner_client, &tx_id, &trader_addr, config.include_mempool)?;
+
+    // Snapshot the mempool while the transfer is still waiting in it
+    if let Some(path) = &config.dump_mempool {
+        explain!("Calling savemempool, then getrawmempool and getrawtransaction to write a replayable dump");
+        let dump = mempool::dump_mempool(&miner_client, path)?;
+        info!(
+            "Saved {} mempool transaction(s) to {}",
+            dump.txs,
+            path.display()
+        );
+        match &dump.node_file {
+            Some(file) => {
+                info!("The node also wrote its own snapshot to {file} (on the node's filesystem)")
+            }
+            None => info!("The node did not report a savemempool file"),
+        }
+    }
+
+    // Confirm transaction by mining blocks on top of it
+    let phase = telemetry::span("confirm");
+    explain!("Calling generatetoaddress again so a block includes, and confirms, the transaction");
+    mining::mine_blocks(&miner_client, &miner_addr, config.confirm_depth)?;
+    info!(
+        "Mined {} block(s) to confirm transaction",
+        config.confirm_depth
+    );
+    print_trader_output(&miner_client, &tx_id, &trader_addr, config.include_mempool)?;
+    wallet::print_balances(
+        "Balances after transfer:",
+        &wallet::all_balances(&wallets)?,
+        display,
+    );
+    explain!("Calling listreceivedbylabel to total what each wallet received per address label");
+    for (name, client) in wallets {
+        wallet::print_received_by_label(name, &wallet::received_by_label(client)?);
+    }
+    if config.reconcile {
+        for (name, client) in wallets {
+            let r = wallet::reconcile_balance(client, Amount::ZERO)?;
+            info!(
+                "{name} balance reconciles: {} BTC trusted = {} BTC in UTXOs ({} BTC immature, {} BTC untrusted pending)",
+                amount::to_btc_string(r.trusted),
+                amount::to_btc_string(r.utxo_total),
+                amount::to_btc_string(r.immature),
+                amount::to_btc_string(r.untrusted_pending)
+            );
+        }
+    }
 
-    // Confirm transaction by mining one block
-    miner_client.generate_to_address(1, &miner_addr)?;
-    println!("Mined a block to confirm transaction");
+    phase.end();
+
+    if let Some(expected) = config.balance_assert {
+        let actual = wallet::assert_balance(&trader_client, expected, Amount::ZERO)?;
+        info!(
+            "{trader_wallet} confirmed balance is {} BTC as expected",
+            amount::to_btc_string(actual)
+        );
+    }
 
     // Extract transaction details for analysis
-    use bitcoincore_rpc::bitcoin::Txid;
-    use std::path::Path;
+    let mut phase = telemetry::span("analysis");
 
     // Fetch confirmed transaction details
+    explain!("Calling gettransaction to find the block that confirmed the payment");
     let tx_details = miner_client.get_transaction(&tx_id, None)?;
     let block_hash = tx_details
         .info
@@ -126,73 +1227,334 @@ fn main() -> bitcoincore_rpc::Result<()> {
         .expect("Expected transaction to be in a block");
     let block_info = miner_client.get_block_info(&block_hash)?;
     let block_height = block_info.height;
+    info!(
+        "Transaction has {} confirmation(s)",
+        tx_details.info.confirmations
+    );
+
+    // The confirming block's coinbase shows the node's real halving schedule
+    let block = miner_client.get_block(&block_hash)?;
+    let subsidy = analysis::check_subsidy(&miner_client, &block, block_height as u64)?;
+    info!(
+        "Block subsidy at height {block_height}: {} BTC configured (halving every {} blocks), {} BTC observed in the coinbase",
+        amount::to_btc_string(subsidy.configured),
+        analysis::halving_interval(),
+        amount::to_btc_string(subsidy.observed)
+    );
+    if !subsidy.matches() {
+        warning!("the configured subsidy differs from the coinbase; pass --halving-interval matching the node's -subsidyhalvinginterval");
+    }
+    info!(
+        "Witness commitment: {}",
+        analysis::verify_witness_commitment(&block)
+    );
 
     // Decode raw transaction
-    let raw_tx = miner_client.get_raw_transaction(&tx_id, Some(&block_hash))?;
-    let decoded_tx = miner_client.decode_raw_transaction(&raw_tx, None)?;
-
-    // Extract input details
-    let input = &decoded_tx.vin[0];
-    let prev_txid = input.txid.expect("Input must have a txid");
-    let prev_vout = input.vout.expect("Input must have a vout") as usize;
-    let prev_tx = miner_client.get_raw_transaction(&prev_txid, None)?;
-    let prev_decoded = miner_client.decode_raw_transaction(&prev_tx, None)?;
-    let prev_output = &prev_decoded.vout[prev_vout];
-    let input_addr = prev_output
-        .script_pub_key
-        .addresses
-        .first()
-        .map(|a| a.clone().assume_checked().to_string())
-        .unwrap_or_default();
-    let input_amount = prev_output.value.to_btc();
+    explain!("Calling getrawtransaction and decoderawtransaction to read its inputs and outputs");
+    let raw_tx = miner_client.get_raw_transaction_hex(&tx_id, Some(&block_hash))?;
+    let decoded_tx = miner_client.decode_raw_transaction(raw_tx.as_str(), None)?;
+
+    // Cross-check the node's decoding against a txid computed locally from the raw bytes
+    analysis::verify_decoded_txid(&raw_tx, &tx_id, &decoded_tx)?;
+
+    // Extract input details, tolerating coinbase inputs without a prevout
+    explain!("Looking up each input's previous output, since a transaction does not record input values itself");
+    let input_summary = analysis::resolve_inputs(
+        &miner_client,
+        &decoded_tx,
+        &block_hash,
+        block_height as u64,
+        node_version,
+    )?;
+    for (index, input) in input_summary.inputs.iter().enumerate() {
+        match input.script_type {
+            Some(script_type) => info!(
+                "  Input {index}: {}, {} BTC, {script_type}",
+                input.address,
+                amount::to_btc_string(input.amount)
+            ),
+            None => info!(
+                "  Input {index}: coinbase, {} BTC subsidy",
+                amount::to_btc_string(input.amount)
+            ),
+        }
+    }
+
+    // Replaceability as the inputs declare it, independent of mempool state
+    if config.include_inputs {
+        info!("Input sequences:");
+        for (index, input) in decoded_tx.vin.iter().enumerate() {
+            let rbf = if analysis::signals_rbf(input.sequence) {
+                "signals RBF"
+            } else {
+                "final, no RBF"
+            };
+            info!("  Input {index}: sequence 0x{:08x}, {rbf}", input.sequence);
+        }
+        let replaceable = decoded_tx
+            .vin
+            .iter()
+            .any(|input| analysis::signals_rbf(input.sequence));
+        info!(
+            "Transaction {} replaceable by its inputs (BIP125)",
+            if replaceable { "is" } else { "is not" }
+        );
+    }
+
+    if config.analyze_block {
+        let block = analysis::analyze_block(&miner_client, &block_hash, node_version)?;
+        info!("Fees in block {} ({}):", block.height, block.hash);
+        for tx in &block.txs {
+            match tx.fee {
+                Some(fee) => info!(
+                    "  {}: {} BTC, {:.2} sat/vB",
+                    tx.txid,
+                    amount::to_btc_string(fee),
+                    fees::fee_rate_sat_vb(fee, tx.vsize)
+                ),
+                None => info!("  {}: coinbase", tx.txid),
+            }
+        }
+        info!("  Total fees: {} BTC", amount::to_btc_string(block.total));
+    }
+
+    if config.trace_prevouts > 0 {
+        info!("Funding ancestry ({} levels):", config.trace_prevouts);
+        let tree = analysis::trace_ancestry(&miner_client, &tx_id, config.trace_prevouts)?;
+        analysis::print_ancestry(&tree, 1);
+    }
 
     // Extract output details: Trader's output and Miner's change
-    let mut trader_out_addr = String::new();
-    let mut trader_out_amount = 0.0;
-    let mut miner_change_addr = String::new();
-    let mut miner_change_amount = 0.0;
-    println!("Transaction outputs:");
+    info!("Transaction outputs:");
     for output in &decoded_tx.vout {
         if let Some(addr) = &output.script_pub_key.address {
-            let addr_str = addr.clone().assume_checked().to_string();
-            let value = output.value.to_btc();
-            println!("  Address: {addr_str}, Amount: {value:.8} BTC");
-            if addr_str == trader_addr.to_string() {
-                trader_out_addr = addr_str.clone();
-                trader_out_amount = value;
-            } else if miner_client
-                .get_address_info(&addr.clone().assume_checked())
-                .map(|info| info.is_mine.unwrap_or(false))
-                .unwrap_or(false)
-            {
-                miner_change_addr = addr_str.clone();
-                miner_change_amount = value;
+            let script_type = analysis::output_script_type(
+                &[&miner_client, &trader_client],
+                &output.script_pub_key,
+            );
+            info!(
+                "  Address: {}, Amount: {} BTC, Type: {script_type}",
+                addr.clone().assume_checked(),
+                amount::to_btc_string(output.value)
+            );
+        }
+    }
+    let outputs = analysis::decoded_outputs(&decoded_tx);
+    let trader_address = trader_addr.to_string();
+    let split = analysis::classify_outputs(&outputs, |address| {
+        if address == trader_address {
+            Some(analysis::Owner::Trader)
+        } else if wallet::owns_address(&miner_client, address) {
+            Some(analysis::Owner::Miner)
+        } else {
+            None
+        }
+    });
+    let mut report = analysis::build_report(
+        tx_id,
+        &input_summary,
+        &outputs,
+        &split,
+        block_height,
+        block_hash,
+    );
+    let fee = report.fee;
+
+    info!("Trader output address: {}", report.trader_address);
+    info!(
+        "Trader output amount: {}",
+        amount::to_btc_string(report.trader_amount)
+    );
+    // The report names the first change address and sums every change output
+    info!("Miner change address: {}", report.change_address);
+    info!(
+        "Miner change amount: {}",
+        amount::to_btc_string(report.change_amount)
+    );
+    let change_outputs = &split.change;
+    if change_outputs.len() > 1 {
+        info!("Change is split across {} outputs:", change_outputs.len());
+        for change in change_outputs {
+            info!(
+                "  vout {}: {} BTC to {}",
+                change.vout,
+                amount::to_btc_string(change.amount),
+                change.address
+            );
+        }
+    }
+
+    if config.privacy_analysis {
+        let groups = analysis::detect_equal_value_outputs(&decoded_tx);
+        if groups.is_empty() {
+            info!("Privacy: no equal-value outputs, so no CoinJoin pattern");
+        } else {
+            info!(
+                "Privacy: {} group(s) of equal-value outputs, a CoinJoin pattern:",
+                groups.len()
+            );
+            for group in &groups {
+                info!(
+                    "  {} BTC x{} (vouts {:?})",
+                    amount::to_btc_string(group.amount),
+                    group.vouts.len(),
+                    group.vouts
+                );
             }
         }
     }
 
-    println!("Trader output address: {trader_out_addr}");
-    println!("Trader output amount: {trader_out_amount:.8}");
-    println!("Miner change address: {miner_change_addr}");
-    println!("Miner change amount: {miner_change_amount:.8}");
+    // An exact spend pays its recipients and nothing comes back to the Miner
+    if config.no_change_expected {
+        info!("Transaction has {} output(s)", decoded_tx.vout.len());
+        if !change_outputs.is_empty() {
+            return Err(AppError::UnexpectedChange(format!(
+                "{} BTC returned to the Miner in {} of {} outputs",
+                amount::to_btc_string(report.change_amount),
+                change_outputs.len(),
+                decoded_tx.vout.len()
+            )));
+        }
+    }
 
-    // Calculate the transaction fee
-    let fee = input_amount - (trader_out_amount + miner_change_amount);
+    if let Some(preview) = &preview {
+        if preview.fee == fee {
+            info!("Fee matches the preview");
+        } else {
+            // Usually the wallet picked different coins than during the preview
+            warning!(
+                "fee of {} BTC differs from the previewed {} BTC",
+                amount::to_btc_string(fee),
+                amount::to_btc_string(preview.fee)
+            );
+        }
+    }
+
+    // Relate the fee rate paid to how quickly the network would confirm it
+    let fee_rate = fees::fee_rate_sat_vb(fee, decoded_tx.vsize);
+    let confirmation_estimate =
+        fees::estimate_confirmation(&miner_client, chain_info.chain, fee_rate)?;
+    info!("Fee rate: {fee_rate:.2} sat/vB, estimated confirmation: {confirmation_estimate}");
+    match fees::feerate_percentile(&miner_client, &tx_id, &block_hash) {
+        Ok(Some(rank)) => {
+            info!("Fee rate within block {block_height}: {rank}");
+            // Guard against fee mistakes: compare with 10x the block's median rate
+            let ceiling_rate = fees::ABSURD_FEE_MULTIPLE * rank.median_rate.max(1) as f64;
+            let ceiling = fees::theoretical_max_fee(&decoded_tx, ceiling_rate);
+            let ratio = fee.to_sat() as f64 / ceiling.to_sat().max(1) as f64;
+            info!(
+                "Fee is {ratio:.2}x the {} BTC ceiling at {ceiling_rate:.0} sat/vB",
+                amount::to_btc_string(ceiling)
+            );
+            if fee > ceiling {
+                warning!(
+                    "fee of {} BTC exceeds {}x the block's median rate; check the fee settings",
+                    amount::to_btc_string(fee),
+                    fees::ABSURD_FEE_MULTIPLE
+                );
+            }
+        }
+        Ok(None) => info!("Block {block_height} holds only its coinbase; no fee rates to compare"),
+        Err(e) => warning!("could not rank the fee rate within block {block_height}: {e}"),
+    }
+
+    if config.include_fee_breakdown {
+        info!("Fee share by input size ({} vB total):", decoded_tx.vsize);
+        for share in fees::per_input_fee_share(&decoded_tx, fee) {
+            info!(
+                "  Input {}: {:.2} vB, {} sat",
+                share.index,
+                share.vbytes,
+                amount::to_sat(share.fee)
+            );
+        }
+    }
+
+    phase.attr("block_height", block_height);
+    phase.attr("fee", amount::to_btc_string(fee));
+    phase.end();
 
     // Write transaction details to output file
-    let output_path = Path::new("../out.txt");
-    let mut file = File::create(output_path)?;
-    writeln!(file, "{tx_id}")?;
-    writeln!(file, "{input_addr}")?;
-    writeln!(file, "{input_amount:.8}")?;
-    writeln!(file, "{trader_out_addr}")?;
-    writeln!(file, "{trader_out_amount:.8}")?;
-    writeln!(file, "{miner_change_addr}")?;
-    writeln!(file, "{miner_change_amount:.8}")?;
-    writeln!(file, "{fee:.8}")?;
-    writeln!(file, "{block_height}")?;
-    writeln!(file, "{block_hash}")?;
-    println!("Saved transaction details to ../out.txt");
+    let phase = telemetry::span("report");
+    report.confirmations = config
+        .extended_report
+        .then_some(tx_details.info.confirmations);
+    report.confirmation_estimate = confirmation_estimate;
+    // Read the earlier report first, in case it is the file about to be replaced
+    let prior = match &config.diff {
+        Some(path) => Some((path, report::read_report_lines(path)?)),
+        None => None,
+    };
+    explain!("Writing the report; the fee is what the inputs hold beyond the outputs");
+    let written = write_report(
+        &report,
+        Path::new(".."),
+        config.format,
+        config.output_append,
+    )?;
+    report::print_summary(&report, display);
+    if let Some(path) = written {
+        info!("Saved transaction details to {}", path.display());
+        if config.verify_report {
+            report::verify_report(&miner_client, &path)?;
+            info!("Verified block hash in {} against the node", path.display());
+        }
+    }
+
+    if let Some(dir) = &config.fixture {
+        fixture::capture(
+            &miner_client,
+            dir,
+            chain_info.chain,
+            &raw_tx,
+            &report,
+            change_outputs,
+        )?;
+        info!("Captured a replayable fixture in {}", dir.display());
+    }
+
+    if let Some((prior, before)) = prior {
+        let diffs = report::diff_reports(&before, &report.lines(), config.strict);
+        if diffs.is_empty() {
+            info!("Report matches {}", prior.display());
+        } else {
+            info!("Changes since {}:", prior.display());
+            for diff in diffs {
+                let delta = diff.delta.map(|d| format!(" ({d})")).unwrap_or_default();
+                info!("  {}: {} -> {}{delta}", diff.field, diff.before, diff.after);
+            }
+        }
+    }
+
+    if config.save_raw_tx {
+        // Keep the raw hex for re-broadcasting or decoding offline, and make
+        // sure what landed on disk still decodes to the same transaction
+        let hex_path = report::write_raw_tx(Path::new(".."), &raw_tx)?;
+        let saved = std::fs::read_to_string(&hex_path)?;
+        let round_trip = miner_client.decode_raw_transaction(saved.trim(), None)?;
+        if round_trip.txid != tx_id {
+            return Err(AppError::TxidMismatch {
+                reported: tx_id.to_string(),
+                recomputed: round_trip.txid.to_string(),
+            });
+        }
+        info!("Saved raw transaction hex to {}", hex_path.display());
+    }
+
+    phase.end();
+
+    // Keep an eye on round-trips so N+1 query patterns do not creep in
+    let calls = rpc::rpc_call_count();
+    info!("RPC round-trips this run: {calls}");
+    // A run with no options is held to the baseline even without --rpc-budget
+    let budget = config
+        .rpc_budget
+        .or(config.args.is_empty().then_some(rpc::BASELINE_CALL_BUDGET));
+    if let Some(budget) = budget {
+        if calls > budget {
+            return Err(AppError::RpcBudgetExceeded { calls, budget });
+        }
+    }
 
     Ok(())
 }
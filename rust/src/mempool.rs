@@ -0,0 +1,261 @@
+// Mempool pressure demonstration: fill the mempool with low-fee filler
+// transactions and watch mempoolminfee rise once the node starts evicting.
+// Core will not set -maxmempool below 5 MB at default limits, so the demo
+// needs a regtest node started with -maxmempool=5 and a target of at least
+// 5000 kvB; fillers take more memory than their vsize, so eviction begins
+// somewhat before that.
+// Also snapshots the mempool to a file and restores it from one.
+use crate::amount;
+use crate::error::{is_method_not_found, AppError};
+use crate::logging::{info, warning};
+use crate::wallet;
+use bitcoincore_rpc::bitcoin::{Address, Amount, OutPoint, Txid};
+use bitcoincore_rpc::json::{AddressType, CreateRawTransactionInput};
+use bitcoincore_rpc::{Client, RpcApi};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+// Value of each filler output, and the fee each filler pays on top
+const FILLER_VALUE: Amount = Amount::from_sat(10_000);
+const FILLER_FEE: Amount = Amount::from_sat(150);
+
+// Virtual size of a one-in one-out P2WPKH filler, about 1.4 sat/vB at FILLER_FEE
+const FILLER_VSIZE: usize = 110;
+
+// Fan-out outputs that fit in one standard transaction (100 kvB)
+const OUTPUTS_PER_FAN_OUT: usize = 2_500;
+
+// Enough fillers to overflow the smallest allowed mempool (5 MB) with room to spare
+const MAX_FILLERS: usize = 60_000;
+
+// The mempool figures the demonstration reports on
+#[derive(Debug, Clone, Copy)]
+pub struct MempoolSnapshot {
+    pub size: usize,
+    pub bytes: usize,
+    pub max_bytes: usize,
+    /// Lowest fee rate accepted, in BTC/kvB
+    pub min_fee: Amount,
+}
+
+pub fn snapshot(rpc: &Client) -> Result<MempoolSnapshot, AppError> {
+    let info = rpc.get_mempool_info()?;
+    Ok(MempoolSnapshot {
+        size: info.size,
+        bytes: info.bytes,
+        max_bytes: info.max_mempool,
+        min_fee: info.mempool_min_fee,
+    })
+}
+
+pub fn print_snapshot(title: &str, snapshot: &MempoolSnapshot) {
+    info!(
+        "{title} {} txs, {} vB of {} bytes allowed, mempoolminfee {} BTC/kvB",
+        snapshot.size,
+        snapshot.bytes,
+        snapshot.max_bytes,
+        amount::to_btc_string(snapshot.min_fee)
+    );
+}
+
+// What happened to the filler transactions
+#[derive(Debug, Default)]
+pub struct FillerStats {
+    pub accepted: usize,
+    /// Refused on arrival, usually because the mempool min fee had risen past them
+    pub rejected: usize,
+    /// Accepted, then pushed out to make room for later arrivals
+    pub evicted: usize,
+}
+
+// Number of outputs in each fan-out transaction needed for `count` fillers
+fn fan_out_batches(count: usize) -> Vec<usize> {
+    (0..count)
+        .step_by(OUTPUTS_PER_FAN_OUT)
+        .map(|start| (count - start).min(OUTPUTS_PER_FAN_OUT))
+        .collect()
+}
+
+// Split wallet funds into `count` confirmed outputs so every filler can spend
+// its own coin; chaining fillers on unconfirmed change would hit the ancestor
+// limit after 25 transactions. Each fan-out transaction carries at most
+// OUTPUTS_PER_FAN_OUT outputs (about 77.5 kvB) and is mined before the next
+// one, which spends its confirmed change: two unconfirmed fan-outs would pass
+// the 101 kvB ancestor size limit.
+fn fan_out(rpc: &Client, count: usize, mine_to: &Address) -> Result<Vec<OutPoint>, AppError> {
+    let mut scripts = HashSet::with_capacity(count);
+    let mut txids = Vec::new();
+    for batch in fan_out_batches(count) {
+        let mut recipients = Vec::with_capacity(batch);
+        for _ in 0..batch {
+            let address = rpc
+                .get_new_address(None, Some(AddressType::Bech32))?
+                .assume_checked();
+            scripts.insert(address.script_pubkey());
+            recipients.push((address.to_string(), FILLER_VALUE));
+        }
+        txids.push(wallet::send_many(rpc, &recipients, &[])?);
+        rpc.generate_to_address(1, mine_to)?;
+    }
+
+    let mut outpoints = Vec::with_capacity(count);
+    for txid in txids {
+        let tx = rpc.get_raw_transaction(&txid, None)?;
+        outpoints.extend(
+            tx.output
+                .iter()
+                .enumerate()
+                .filter(|(_, output)| scripts.contains(&output.script_pubkey))
+                .map(|(vout, _)| OutPoint::new(txid, vout as u32)),
+        );
+    }
+    if outpoints.len() < count {
+        return Err(AppError::FanOutShort {
+            created: outpoints.len(),
+            wanted: count,
+        });
+    }
+    Ok(outpoints)
+}
+
+// Spend one fan-out output back to the wallet, paying only FILLER_FEE
+fn send_filler(rpc: &Client, outpoint: OutPoint) -> bitcoincore_rpc::Result<Txid> {
+    let address = rpc.get_new_address(None, Some(AddressType::Bech32))?;
+    let input = CreateRawTransactionInput {
+        txid: outpoint.txid,
+        vout: outpoint.vout,
+        sequence: None,
+    };
+    let outputs = HashMap::from([(
+        address.assume_checked().to_string(),
+        FILLER_VALUE - FILLER_FEE,
+    )]);
+    let unsigned = rpc.create_raw_transaction_hex(&[input], &outputs, None, None)?;
+    let signed = rpc.sign_raw_transaction_with_wallet(unsigned, None, None)?;
+    rpc.send_raw_transaction(&signed.hex)
+}
+
+// Broadcast enough low-fee fillers to add about `target_bytes` vbytes to the
+// mempool. With `-maxmempool` set below that, the node evicts the cheapest
+// transactions and raises mempoolminfee.
+pub fn fill_mempool(
+    rpc: &Client,
+    mine_to: &Address,
+    target_bytes: usize,
+) -> Result<FillerStats, AppError> {
+    let wanted = target_bytes / FILLER_VSIZE + 1;
+    let count = wanted.min(MAX_FILLERS);
+    if count < wanted {
+        warning!("capping the demonstration at {MAX_FILLERS} filler transactions");
+    }
+
+    let mut stats = FillerStats::default();
+    let mut accepted = Vec::with_capacity(count);
+    for outpoint in fan_out(rpc, count, mine_to)? {
+        match send_filler(rpc, outpoint) {
+            Ok(txid) => accepted.push(txid),
+            Err(_) => stats.rejected += 1,
+        }
+    }
+    stats.accepted = accepted.len();
+    stats.evicted = accepted
+        .iter()
+        .filter(|txid| rpc.get_mempool_entry(txid).is_err())
+        .count();
+    Ok(stats)
+}
+
+// What --dump-mempool wrote
+#[derive(Debug)]
+pub struct MempoolDump {
+    pub txs: usize,
+    /// Where savemempool put mempool.dat, on the node's own filesystem;
+    /// None on nodes that lack the RPC or do not report the file
+    pub node_file: Option<String>,
+}
+
+// Save the mempool twice: through savemempool into the node's datadir, and as
+// raw transactions in `path`, one hex per line with parents before children
+// so a replay never sends a transaction ahead of its inputs
+pub fn dump_mempool(rpc: &Client, path: &Path) -> Result<MempoolDump, AppError> {
+    let node_file = match rpc.call::<serde_json::Value>("savemempool", &[]) {
+        // Core 23+ answers {"filename": ...}; older releases answer null
+        Ok(saved) => saved["filename"].as_str().map(str::to_string),
+        Err(e) if is_method_not_found(&e) => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut entries: Vec<_> = rpc.get_raw_mempool_verbose()?.into_iter().collect();
+    // An ancestor always has fewer in-mempool ancestors than its descendants
+    entries.sort_by_key(|(txid, entry)| (entry.ancestor_count, *txid));
+    let mut lines = Vec::with_capacity(entries.len());
+    for (txid, _) in &entries {
+        match rpc.get_raw_transaction_hex(txid, None) {
+            Ok(hex) => lines.push(hex),
+            // Mined or evicted since getrawmempool listed it
+            Err(_) => warning!("{txid} left the mempool while dumping it"),
+        }
+    }
+    let mut file = File::create(path)?;
+    for line in &lines {
+        writeln!(file, "{line}")?;
+    }
+    Ok(MempoolDump {
+        txs: lines.len(),
+        node_file,
+    })
+}
+
+// Outcome of re-broadcasting a dump
+#[derive(Debug, Default)]
+pub struct MempoolLoad {
+    pub loaded: usize,
+    /// Already mined, conflicting with the chain, or refused by policy
+    pub rejected: usize,
+}
+
+// Re-broadcast every transaction in a --dump-mempool file. The node's own
+// mempool.dat is only read at startup (-persistmempool), so restoring a
+// snapshot into a running node means sending the transactions again.
+pub fn load_mempool(rpc: &Client, path: &Path) -> Result<MempoolLoad, AppError> {
+    let mut load = MempoolLoad::default();
+    for hex in fs::read_to_string(path)?.lines().map(str::trim) {
+        if hex.is_empty() {
+            continue;
+        }
+        match rpc.send_raw_transaction(hex) {
+            Ok(_) => load.loaded += 1,
+            Err(e) => {
+                warning!("could not re-broadcast a dumped transaction: {e}");
+                load.rejected += 1;
+            }
+        }
+    }
+    Ok(load)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batches_cover_every_filler() {
+        assert!(fan_out_batches(0).is_empty());
+        assert_eq!(fan_out_batches(2_500), [2_500]);
+        assert_eq!(fan_out_batches(5_001), [2_500, 2_500, 1]);
+    }
+
+    #[test]
+    fn documented_target_fits_under_the_cap() {
+        // -maxmempool=5 needs at least 5000 kvB of fillers to start evicting
+        let target_bytes = 5_000 * 1_000;
+        let count = target_bytes / FILLER_VSIZE + 1;
+        assert!(count <= MAX_FILLERS);
+        assert!(count * FILLER_VSIZE >= target_bytes);
+        let batches = fan_out_batches(count);
+        assert_eq!(batches.iter().sum::<usize>(), count);
+        assert!(batches.iter().all(|&b| b <= OUTPUTS_PER_FAN_OUT));
+    }
+}
@@ -0,0 +1,106 @@
+// Block generation helpers and the coinbase maturity rule
+use crate::amount;
+use crate::error::AppError;
+use bitcoincore_rpc::bitcoin::{Address, BlockHash};
+use bitcoincore_rpc::{Client, RpcApi};
+
+// A coinbase output can be spent once it is buried under 100 more blocks
+pub const COINBASE_MATURITY: u64 = 100;
+
+// Mine `count` blocks to `address`, returning their hashes in chain order
+pub fn mine_blocks(
+    rpc: &Client,
+    address: &Address,
+    count: u64,
+) -> Result<Vec<BlockHash>, AppError> {
+    Ok(rpc.generate_to_address(count, address)?)
+}
+
+// Blocks mined without a spendable balance after which mining warns that
+// the --max-blocks allowance is running out: with the last tenth left, and
+// always at least one block before the cap
+pub fn stall_warning_at(max_blocks: u64) -> u64 {
+    max_blocks.saturating_sub((max_blocks / 10).max(1))
+}
+
+// Chain heights either side of the first coinbase becoming spendable
+#[derive(Debug, Clone, Copy)]
+pub struct MaturityBoundary {
+    /// Height of the block whose coinbase is being matured
+    pub coinbase_height: u64,
+    /// Tip height at which the reward was still immature
+    pub immature_height: u64,
+    /// Tip height at which the reward first became spendable
+    pub mature_height: u64,
+}
+
+// Mine exactly COINBASE_MATURITY blocks to `address` and check the reward is
+// still immature, then mine one more and check it became spendable. The wallet
+// must hold no coinbase rewards beforehand, or the transition is not observable.
+pub fn assert_maturity_boundary(
+    rpc: &Client,
+    address: &Address,
+) -> Result<MaturityBoundary, AppError> {
+    let start = rpc.get_balances()?.mine;
+    if start.trusted.to_sat() != 0 || start.immature.to_sat() != 0 {
+        return Err(AppError::MaturityViolation(format!(
+            "wallet already holds {} BTC spendable and {} BTC immature",
+            amount::to_btc_string(start.trusted),
+            amount::to_btc_string(start.immature)
+        )));
+    }
+    let coinbase_height = rpc.get_block_count()? + 1;
+
+    rpc.generate_to_address(COINBASE_MATURITY, address)?;
+    let immature_height = rpc.get_block_count()?;
+    let before = rpc.get_balances()?.mine;
+    if before.immature.to_sat() == 0 {
+        return Err(AppError::MaturityViolation(format!(
+            "no immature balance was present before block {}; the {COINBASE_MATURITY} mined rewards never reached the wallet",
+            COINBASE_MATURITY + 1
+        )));
+    }
+    if before.trusted.to_sat() != 0 {
+        return Err(AppError::MaturityViolation(format!(
+            "after {COINBASE_MATURITY} blocks expected only immature funds, found {} BTC spendable",
+            amount::to_btc_string(before.trusted)
+        )));
+    }
+
+    rpc.generate_to_address(1, address)?;
+    let mature_height = rpc.get_block_count()?;
+    let after = rpc.get_balances()?.mine;
+    if after.trusted.to_sat() == 0 {
+        return Err(AppError::MaturityViolation(format!(
+            "after {} blocks the first reward is still not spendable",
+            COINBASE_MATURITY + 1
+        )));
+    }
+
+    Ok(MaturityBoundary {
+        coinbase_height,
+        immature_height,
+        mature_height,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stall_warning_comes_before_the_cap() {
+        assert_eq!(stall_warning_at(1_000), 900);
+        assert_eq!(stall_warning_at(101), 91);
+        assert_eq!(stall_warning_at(10), 9);
+        // Below ten blocks a tenth rounds to zero, which would land on the cap
+        assert_eq!(stall_warning_at(9), 8);
+        assert_eq!(stall_warning_at(2), 1);
+        assert_eq!(stall_warning_at(1), 0);
+        assert_eq!(stall_warning_at(0), 0);
+        for max_blocks in 2..200 {
+            let warn_at = stall_warning_at(max_blocks);
+            assert!(warn_at > 0 && warn_at < max_blocks, "{max_blocks}");
+        }
+    }
+}
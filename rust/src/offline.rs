@@ -0,0 +1,294 @@
+// Airgapped signing: build and sign a transaction from known UTXOs and keys
+// with the `bitcoin` crate alone, so the node is only needed to broadcast it.
+// Built with the `offline-sign` feature and run with --offline-sign SPEC.json.
+// Only P2WPKH inputs are supported.
+use crate::error::AppError;
+use bitcoin::consensus::encode::serialize_hex;
+use bitcoin::ecdsa;
+use bitcoin::secp256k1::{Message, Secp256k1};
+use bitcoin::sighash::{EcdsaSighashType, SighashCache};
+use bitcoin::transaction::Version;
+use bitcoin::{
+    absolute, Address, Amount, CompressedPublicKey, Denomination, Network, OutPoint, PrivateKey,
+    ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
+use bitcoincore_rpc::{Client, RpcApi};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+// What --offline-sign reads: the coins, their keys and where to pay. Amounts
+// are BTC decimal strings, keys WIF, and the network applies to every address.
+//   {"network": "regtest",
+//    "utxos": [{"txid": "..", "vout": 0, "amount": "50", "script_pubkey": "0014.."}],
+//    "keys": ["cT.."],
+//    "outputs": [{"address": "bcrt1q..", "amount": "49.9999"}]}
+#[derive(Debug, Deserialize)]
+struct SpendSpec {
+    network: String,
+    utxos: Vec<UtxoSpec>,
+    keys: Vec<String>,
+    outputs: Vec<OutputSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UtxoSpec {
+    txid: String,
+    vout: u32,
+    amount: String,
+    script_pubkey: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OutputSpec {
+    address: String,
+    amount: String,
+}
+
+// A spend read from a spec file, ready for `build_signed_tx`
+#[derive(Debug)]
+pub struct OfflineSpend {
+    pub utxos: Vec<OfflineUtxo>,
+    pub keys: Vec<PrivateKey>,
+    pub outputs: Vec<(ScriptBuf, Amount)>,
+}
+
+// Read and check a --offline-sign spec file
+pub fn read_spend(path: &Path) -> Result<OfflineSpend, AppError> {
+    let invalid = |what: String| AppError::Decode(format!("{}: {what}", path.display()));
+    let spec: SpendSpec =
+        serde_json::from_str(&fs::read_to_string(path)?).map_err(|e| invalid(e.to_string()))?;
+    let network = Network::from_str(&spec.network)
+        .map_err(|e| invalid(format!("network '{}': {e}", spec.network)))?;
+    let amount = |value: &str| {
+        Amount::from_str_in(value, Denomination::Bitcoin)
+            .map_err(|e| invalid(format!("amount '{value}': {e}")))
+    };
+
+    let mut utxos = Vec::with_capacity(spec.utxos.len());
+    for utxo in &spec.utxos {
+        let txid = Txid::from_str(&utxo.txid)
+            .map_err(|e| invalid(format!("txid '{}': {e}", utxo.txid)))?;
+        let script_pubkey = ScriptBuf::from_hex(&utxo.script_pubkey)
+            .map_err(|e| invalid(format!("script_pubkey '{}': {e}", utxo.script_pubkey)))?;
+        utxos.push(OfflineUtxo {
+            outpoint: OutPoint::new(txid, utxo.vout),
+            amount: amount(&utxo.amount)?,
+            script_pubkey,
+        });
+    }
+    let keys = spec
+        .keys
+        .iter()
+        .map(|wif| PrivateKey::from_wif(wif).map_err(|e| invalid(format!("key: {e}"))))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut outputs = Vec::with_capacity(spec.outputs.len());
+    for output in &spec.outputs {
+        let address = Address::from_str(&output.address)
+            .and_then(|a| a.require_network(network))
+            .map_err(|e| invalid(format!("address '{}': {e}", output.address)))?;
+        outputs.push((address.script_pubkey(), amount(&output.amount)?));
+    }
+    Ok(OfflineSpend {
+        utxos,
+        keys,
+        outputs,
+    })
+}
+
+// A coin to spend, described fully enough to sign for it without a node
+#[derive(Debug, Clone)]
+pub struct OfflineUtxo {
+    pub outpoint: OutPoint,
+    pub amount: Amount,
+    pub script_pubkey: ScriptBuf,
+}
+
+// Spend every UTXO to `outputs`, leaving the difference as the fee. Each UTXO
+// is signed with whichever of `keys` controls its P2WPKH script.
+pub fn build_signed_tx(
+    utxos: &[OfflineUtxo],
+    keys: &[PrivateKey],
+    outputs: &[(ScriptBuf, Amount)],
+) -> Result<Transaction, AppError> {
+    let input_total: Amount = utxos.iter().map(|u| u.amount).sum();
+    let output_total: Amount = outputs.iter().map(|(_, amount)| *amount).sum();
+    if output_total > input_total {
+        return Err(AppError::Usage(format!(
+            "outputs total {output_total} but the UTXOs only hold {input_total}"
+        )));
+    }
+
+    let secp = Secp256k1::new();
+    let signers = utxos
+        .iter()
+        .map(|utxo| {
+            keys.iter()
+                .find_map(|key| {
+                    let public = CompressedPublicKey::from_private_key(&secp, key).ok()?;
+                    let script = ScriptBuf::new_p2wpkh(&public.wpubkey_hash());
+                    (script == utxo.script_pubkey).then_some((key, public))
+                })
+                .ok_or_else(|| {
+                    AppError::Unsupported(format!(
+                        "no key for {}, or it is not a P2WPKH output",
+                        utxo.outpoint
+                    ))
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut tx = Transaction {
+        version: Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: utxos
+            .iter()
+            .map(|utxo| TxIn {
+                previous_output: utxo.outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            })
+            .collect(),
+        output: outputs
+            .iter()
+            .map(|(script_pubkey, value)| TxOut {
+                value: *value,
+                script_pubkey: script_pubkey.clone(),
+            })
+            .collect(),
+    };
+
+    let mut witnesses = Vec::with_capacity(utxos.len());
+    let mut cache = SighashCache::new(&tx);
+    for (index, (utxo, (key, public))) in utxos.iter().zip(&signers).enumerate() {
+        let sighash = cache
+            .p2wpkh_signature_hash(
+                index,
+                &utxo.script_pubkey,
+                utxo.amount,
+                EcdsaSighashType::All,
+            )
+            .map_err(|e| AppError::Decode(format!("cannot compute sighash: {e}")))?;
+        let signature = ecdsa::Signature {
+            signature: secp.sign_ecdsa(&Message::from(sighash), &key.inner),
+            sighash_type: EcdsaSighashType::All,
+        };
+        witnesses.push(Witness::p2wpkh(&signature, &public.0));
+    }
+    for (input, witness) in tx.input.iter_mut().zip(witnesses) {
+        input.witness = witness;
+    }
+    Ok(tx)
+}
+
+// Check the signed transaction with testmempoolaccept, then broadcast it.
+// This is the only step that talks to the node.
+pub fn broadcast(
+    rpc: &Client,
+    tx: &Transaction,
+) -> Result<bitcoincore_rpc::bitcoin::Txid, AppError> {
+    let hex = serialize_hex(tx);
+    let verdict = rpc.test_mempool_accept(&[hex.as_str()])?;
+    if let Some(rejected) = verdict.iter().find(|r| !r.allowed) {
+        return Err(AppError::Unsupported(format!(
+            "node would reject the offline transaction: {}",
+            rejected
+                .reject_reason
+                .as_deref()
+                .unwrap_or("no reason given")
+        )));
+    }
+    Ok(rpc.send_raw_transaction(hex.as_str())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::secp256k1::SecretKey;
+
+    fn key(byte: u8) -> PrivateKey {
+        PrivateKey::new(
+            SecretKey::from_slice(&[byte; 32]).unwrap(),
+            Network::Regtest,
+        )
+    }
+
+    fn utxo_for(key: &PrivateKey, amount: Amount) -> OfflineUtxo {
+        let public = CompressedPublicKey::from_private_key(&Secp256k1::new(), key).unwrap();
+        OfflineUtxo {
+            outpoint: OutPoint::new(Txid::all_zeros(), 0),
+            amount,
+            script_pubkey: ScriptBuf::new_p2wpkh(&public.wpubkey_hash()),
+        }
+    }
+
+    #[test]
+    fn signs_a_p2wpkh_spend() {
+        let key = key(1);
+        let utxo = utxo_for(&key, Amount::from_sat(100_000));
+        let destination = utxo_for(&self::key(2), Amount::ZERO).script_pubkey;
+        let outputs = [(destination, Amount::from_sat(99_000))];
+        let tx = build_signed_tx(std::slice::from_ref(&utxo), &[key], &outputs).unwrap();
+
+        assert_eq!(tx.output[0].value, Amount::from_sat(99_000));
+        let witness = &tx.input[0].witness;
+        assert_eq!(witness.len(), 2);
+
+        // The witness signature verifies against the input's sighash
+        let secp = Secp256k1::new();
+        let public = CompressedPublicKey::from_private_key(&secp, &key).unwrap();
+        assert_eq!(witness.nth(1).unwrap(), public.to_bytes());
+        let signature = ecdsa::Signature::from_slice(witness.nth(0).unwrap()).unwrap();
+        let sighash = SighashCache::new(&tx)
+            .p2wpkh_signature_hash(0, &utxo.script_pubkey, utxo.amount, EcdsaSighashType::All)
+            .unwrap();
+        secp.verify_ecdsa(&Message::from(sighash), &signature.signature, &public.0)
+            .unwrap();
+    }
+
+    #[test]
+    fn refuses_utxos_without_a_key() {
+        let utxo = utxo_for(&key(1), Amount::from_sat(100_000));
+        let outputs = [(utxo.script_pubkey.clone(), Amount::from_sat(1_000))];
+        assert!(build_signed_tx(&[utxo], &[key(2)], &outputs).is_err());
+    }
+
+    #[test]
+    fn refuses_outputs_above_the_inputs() {
+        let key = key(1);
+        let utxo = utxo_for(&key, Amount::from_sat(1_000));
+        let outputs = [(utxo.script_pubkey.clone(), Amount::from_sat(1_001))];
+        assert!(build_signed_tx(&[utxo], &[key], &outputs).is_err());
+    }
+
+    #[test]
+    fn reads_a_spend_spec() {
+        let key = key(1);
+        let public = CompressedPublicKey::from_private_key(&Secp256k1::new(), &key).unwrap();
+        let address = Address::p2wpkh(&public, Network::Regtest);
+        let spec = serde_json::json!({
+            "network": "regtest",
+            "utxos": [{
+                "txid": Txid::all_zeros().to_string(),
+                "vout": 1,
+                "amount": "50",
+                "script_pubkey": address.script_pubkey().to_hex_string(),
+            }],
+            "keys": [key.to_wif()],
+            "outputs": [{"address": address.to_string(), "amount": "49.9999"}],
+        });
+        let path = std::env::temp_dir().join(format!("offline-spec-{}.json", std::process::id()));
+        fs::write(&path, spec.to_string()).unwrap();
+        let spend = read_spend(&path);
+        fs::remove_file(&path).unwrap();
+
+        let spend = spend.unwrap();
+        assert_eq!(spend.utxos[0].outpoint.vout, 1);
+        assert_eq!(spend.utxos[0].amount, Amount::from_sat(5_000_000_000));
+        assert_eq!(spend.keys, [key]);
+        assert_eq!(spend.outputs[0].1, Amount::from_sat(4_999_990_000));
+    }
+}
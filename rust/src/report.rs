@@ -0,0 +1,424 @@
+// The transaction report written to out.txt
+use crate::amount::{self, AmountFormatter, Unit};
+use crate::error::AppError;
+use crate::fees::ConfirmationEstimate;
+use crate::logging::info;
+use bitcoincore_rpc::bitcoin::{Amount, BlockHash, Txid};
+use bitcoincore_rpc::{Client, RpcApi};
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone)]
+pub struct TransactionReport {
+    pub txid: Txid,
+    pub input_address: String,
+    pub input_amount: Amount,
+    pub trader_address: String,
+    pub trader_amount: Amount,
+    pub change_address: String,
+    pub change_amount: Amount,
+    pub fee: Amount,
+    pub block_height: usize,
+    pub block_hash: BlockHash,
+    /// Confirmations when the report was made; set only with --extended-report
+    pub confirmations: Option<i32>,
+    // Informational only; not part of the ten-line out.txt format
+    pub confirmation_estimate: ConfirmationEstimate,
+}
+
+impl TransactionReport {
+    // One attribute per line, in the order the autograder expects. An
+    // extended report adds the confirmation count as an eleventh line.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            self.txid.to_string(),
+            self.input_address.clone(),
+            amount::to_btc_string(self.input_amount),
+            self.trader_address.clone(),
+            amount::to_btc_string(self.trader_amount),
+            self.change_address.clone(),
+            amount::to_btc_string(self.change_amount),
+            amount::to_btc_string(self.fee),
+            self.block_height.to_string(),
+            self.block_hash.to_string(),
+        ];
+        lines.extend(self.confirmations.map(|c| c.to_string()));
+        lines
+    }
+
+    // Names of the fields `lines` returns, in the same order
+    pub fn field_names(&self) -> Vec<&'static str> {
+        let mut names = FIELD_NAMES.to_vec();
+        if self.confirmations.is_some() {
+            names.push(EXTENDED_FIELD);
+        }
+        names
+    }
+}
+
+// Human-readable recap of the report on stdout, amounts shown with
+// `formatter`; the report files are unaffected by the choice
+pub fn print_summary(report: &TransactionReport, formatter: &dyn AmountFormatter) {
+    info!("Summary:");
+    info!("  Transaction: {}", report.txid);
+    info!(
+        "  Spent:       {} BTC from {}",
+        formatter.format(report.input_amount),
+        report.input_address
+    );
+    info!(
+        "  Paid:        {} BTC to {}",
+        formatter.format(report.trader_amount),
+        report.trader_address
+    );
+    info!(
+        "  Change:      {} BTC to {}",
+        formatter.format(report.change_amount),
+        report.change_address
+    );
+    info!("  Fee:         {} BTC", formatter.format(report.fee));
+    info!(
+        "  Confirmed:   block {} ({})",
+        report.block_height, report.block_hash
+    );
+}
+
+// Serialises a report in one output format
+pub trait OutputFormat {
+    fn write(&self, report: &TransactionReport, writer: &mut dyn Write) -> io::Result<()>;
+}
+
+// The ten-line format checked by the autograder
+pub struct TextFormat;
+
+impl OutputFormat for TextFormat {
+    fn write(&self, report: &TransactionReport, writer: &mut dyn Write) -> io::Result<()> {
+        for line in report.lines() {
+            writeln!(writer, "{line}")?;
+        }
+        Ok(())
+    }
+}
+
+// A single JSON object keyed by field name
+pub struct JsonFormat;
+
+impl OutputFormat for JsonFormat {
+    fn write(&self, report: &TransactionReport, writer: &mut dyn Write) -> io::Result<()> {
+        let object: serde_json::Map<_, _> = report
+            .field_names()
+            .iter()
+            .map(|name| name.to_string())
+            .zip(report.lines().into_iter().map(serde_json::Value::String))
+            .collect();
+        serde_json::to_writer_pretty(&mut *writer, &object)?;
+        writeln!(writer)
+    }
+}
+
+// A header row followed by one row of values
+pub struct CsvFormat;
+
+impl OutputFormat for CsvFormat {
+    fn write(&self, report: &TransactionReport, writer: &mut dyn Write) -> io::Result<()> {
+        writeln!(writer, "{}", report.field_names().join(","))?;
+        writeln!(writer, "{}", report.lines().join(","))
+    }
+}
+
+// Labelled lines for reading in a terminal
+pub struct StdoutFormat;
+
+impl OutputFormat for StdoutFormat {
+    fn write(&self, report: &TransactionReport, writer: &mut dyn Write) -> io::Result<()> {
+        for (name, value) in report.field_names().iter().zip(report.lines()) {
+            writeln!(writer, "{name:>14}: {value}")?;
+        }
+        writeln!(
+            writer,
+            "{:>14}: {}",
+            "confirmation", report.confirmation_estimate
+        )
+    }
+}
+
+// Field names in report order, used as JSON keys, CSV headers and labels
+pub const FIELD_NAMES: [&str; 10] = [
+    "txid",
+    "input_address",
+    "input_amount",
+    "trader_address",
+    "trader_amount",
+    "change_address",
+    "change_amount",
+    "fee",
+    "block_height",
+    "block_hash",
+];
+
+// The extended format (--extended-report) is the ten standard fields followed
+// by this one: the transaction's confirmation count from gettransaction at the
+// time the report was written. It is line 11 of out.txt, a "confirmations" key
+// in out.json and a trailing column in out.csv.
+pub const EXTENDED_FIELD: &str = "confirmations";
+
+// Output format selected with --format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReportFormat {
+    #[default]
+    Txt,
+    Json,
+    Csv,
+    Stdout,
+}
+
+impl ReportFormat {
+    pub fn formatter(self) -> &'static dyn OutputFormat {
+        match self {
+            ReportFormat::Txt => &TextFormat,
+            ReportFormat::Json => &JsonFormat,
+            ReportFormat::Csv => &CsvFormat,
+            ReportFormat::Stdout => &StdoutFormat,
+        }
+    }
+
+    // File the format is written to, or `None` when it goes to stdout
+    pub fn file_name(self) -> Option<&'static str> {
+        match self {
+            ReportFormat::Txt => Some("out.txt"),
+            ReportFormat::Json => Some("out.json"),
+            ReportFormat::Csv => Some("out.csv"),
+            ReportFormat::Stdout => None,
+        }
+    }
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "txt" => Ok(ReportFormat::Txt),
+            "json" => Ok(ReportFormat::Json),
+            "csv" => Ok(ReportFormat::Csv),
+            "stdout" => Ok(ReportFormat::Stdout),
+            other => Err(format!("expected txt, json, csv or stdout, got '{other}'")),
+        }
+    }
+}
+
+// Write the report in `format` under `dir`, returning the file written, if any.
+// By default the file is replaced; with `append` the report is added as a new
+// block headed by a timestamped separator, so a series of runs can be logged
+// to one file.
+pub fn write_report(
+    report: &TransactionReport,
+    dir: &Path,
+    format: ReportFormat,
+    append: bool,
+) -> io::Result<Option<PathBuf>> {
+    let formatter = format.formatter();
+    let Some(file_name) = format.file_name() else {
+        formatter.write(report, &mut io::stdout().lock())?;
+        return Ok(None);
+    };
+
+    let path = dir.join(file_name);
+    let mut file = if append {
+        OpenOptions::new().create(true).append(true).open(&path)?
+    } else {
+        OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&path)?
+    };
+
+    if append {
+        writeln!(file, "----- {} -----", utc_timestamp())?;
+    }
+    formatter.write(report, &mut file)?;
+    Ok(Some(path))
+}
+
+// Check the newest report in an out.txt file against the chain: the recorded
+// block hash must be the block the node has at the recorded height
+pub fn verify_report(rpc: &Client, path: &Path) -> Result<(), AppError> {
+    let report = read_report_lines(path)?;
+    let height: u64 = report[8]
+        .trim()
+        .parse()
+        .map_err(|e| AppError::Decode(format!("invalid block height '{}': {e}", report[8])))?;
+    let recorded = BlockHash::from_str(report[9].trim())
+        .map_err(|e| AppError::Decode(format!("invalid block hash '{}': {e}", report[9])))?;
+
+    let expected = rpc.get_block_hash(height)?;
+    if recorded != expected {
+        return Err(AppError::BlockHashMismatch {
+            height,
+            recorded: recorded.to_string(),
+            expected: expected.to_string(),
+        });
+    }
+    Ok(())
+}
+
+// The ten lines of the newest report in an out.txt file. With
+// --output-append the file holds several reports, the newest last.
+pub fn read_report_lines(path: &Path) -> Result<Vec<String>, AppError> {
+    let contents = std::fs::read_to_string(path)?;
+    let lines: Vec<&str> = contents.lines().collect();
+    let Some(start) = lines.len().checked_sub(FIELD_NAMES.len()) else {
+        return Err(AppError::Decode(format!(
+            "{} holds fewer than {} lines",
+            path.display(),
+            FIELD_NAMES.len()
+        )));
+    };
+    Ok(lines[start..]
+        .iter()
+        .map(|l| l.trim().to_string())
+        .collect())
+}
+
+// One field whose value differs between two reports
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldDiff {
+    pub field: &'static str,
+    pub before: String,
+    pub after: String,
+    /// Signed change for amount and height fields, e.g. "+0.00000141"
+    pub delta: Option<String>,
+}
+
+// Field-by-field differences from report `a` to report `b`, both given as
+// report lines. The txid differs on every run, so it is only compared when
+// `strict` is set.
+pub fn diff_reports(a: &[String], b: &[String], strict: bool) -> Vec<FieldDiff> {
+    FIELD_NAMES
+        .iter()
+        .zip(a.iter().zip(b))
+        .filter(|(field, (before, after))| (strict || **field != "txid") && before != after)
+        .map(|(field, (before, after))| FieldDiff {
+            field,
+            before: before.clone(),
+            after: after.clone(),
+            delta: field_delta(field, before, after),
+        })
+        .collect()
+}
+
+// Difference of two numeric field values, or `None` for non-numeric fields
+// and values that do not parse
+fn field_delta(field: &str, before: &str, after: &str) -> Option<String> {
+    if field == "block_height" {
+        let (before, after) = (before.parse::<i64>().ok()?, after.parse::<i64>().ok()?);
+        return Some(format!("{:+}", after - before));
+    }
+    if !field.ends_with("amount") && field != "fee" {
+        return None;
+    }
+    let before = amount::parse_amount(before, Unit::Btc).ok()?.to_sat() as i64;
+    let after = amount::parse_amount(after, Unit::Btc).ok()?.to_sat() as i64;
+    let change = after - before;
+    let sign = if change < 0 { "-" } else { "+" };
+    Some(format!(
+        "{sign}{}",
+        amount::to_btc_string(Amount::from_sat(change.unsigned_abs()))
+    ))
+}
+
+// Write the raw transaction hex next to the report as out.hex
+pub fn write_raw_tx(dir: &Path, raw_hex: &str) -> io::Result<PathBuf> {
+    let path = dir.join("out.hex");
+    std::fs::write(&path, format!("{raw_hex}\n"))?;
+    Ok(path)
+}
+
+// Write an unsigned PSBT (base64) awaiting external signing as out.psbt
+pub fn write_psbt(dir: &Path, psbt: &str) -> io::Result<PathBuf> {
+    let path = dir.join("out.psbt");
+    std::fs::write(&path, format!("{psbt}\n"))?;
+    Ok(path)
+}
+
+// Current time as an ISO 8601 UTC timestamp, e.g. 2024-01-31T12:00:00Z
+fn utc_timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // Civil date from days since the epoch (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoincore_rpc::bitcoin::hashes::Hash;
+
+    fn sample_report() -> TransactionReport {
+        TransactionReport {
+            txid: Txid::all_zeros(),
+            input_address: "bcrt1qinput".to_string(),
+            input_amount: Amount::from_sat(5_000_000_000),
+            trader_address: "bcrt1qtrader".to_string(),
+            trader_amount: Amount::from_sat(2_000_000_000),
+            change_address: "bcrt1qchange".to_string(),
+            change_amount: Amount::from_sat(2_999_998_590),
+            fee: Amount::from_sat(1_410),
+            block_height: 102,
+            block_hash: BlockHash::all_zeros(),
+            confirmations: None,
+            confirmation_estimate: ConfirmationEstimate::Immediate,
+        }
+    }
+
+    fn written(format: ReportFormat) -> String {
+        let mut buffer = Vec::new();
+        format
+            .formatter()
+            .write(&sample_report(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn every_format_writes_output() {
+        for format in [
+            ReportFormat::Txt,
+            ReportFormat::Json,
+            ReportFormat::Csv,
+            ReportFormat::Stdout,
+        ] {
+            let output = written(format);
+            assert!(!output.is_empty(), "{format:?} wrote nothing");
+            assert!(output.contains("29.99998590"), "{format:?}");
+        }
+    }
+
+    #[test]
+    fn text_format_is_ten_lines() {
+        assert_eq!(written(ReportFormat::Txt).lines().count(), 10);
+    }
+}
@@ -0,0 +1,98 @@
+// Wallet rescans: starting one, watching its progress and aborting it
+use crate::error::AppError;
+use crate::logging::info;
+use bitcoincore_rpc::json::ScanningDetails;
+use bitcoincore_rpc::{Client, RpcApi};
+use std::thread;
+use std::time::Duration;
+
+// How often a running rescan's progress is polled
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// Whether the wallet is currently rescanning, as reported by getwalletinfo
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RescanState {
+    Idle,
+    Scanning { progress: f32, duration: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RescanOutcome {
+    Completed {
+        start_height: usize,
+        stop_height: Option<usize>,
+    },
+    /// Stopped by abortrescan after covering this fraction of the range
+    Aborted { progress: f32 },
+}
+
+pub fn rescan_state(rpc: &Client) -> Result<RescanState, AppError> {
+    Ok(match rpc.get_wallet_info()?.scanning {
+        Some(ScanningDetails::Scanning { duration, progress }) => {
+            RescanState::Scanning { progress, duration }
+        }
+        _ => RescanState::Idle,
+    })
+}
+
+// Run rescanblockchain from `start_height` on `worker`, which blocks until the
+// rescan ends, while polling progress through `monitor`. Both clients must
+// point at the same wallet.
+pub fn rescan(
+    worker: Client,
+    monitor: &Client,
+    start_height: usize,
+) -> Result<RescanOutcome, AppError> {
+    if let RescanState::Scanning { progress, .. } = rescan_state(monitor)? {
+        return Err(AppError::Unsupported(format!(
+            "a rescan is already {:.0}% done; stop it with --abort-rescan",
+            progress * 100.0
+        )));
+    }
+
+    // Convert the error on the worker thread, where its call context is recorded
+    let handle = thread::spawn(move || {
+        worker
+            .rescan_blockchain(Some(start_height), None)
+            .map_err(AppError::from)
+    });
+    let mut last_progress = 0.0;
+    while !handle.is_finished() {
+        thread::sleep(POLL_INTERVAL);
+        if let Ok(RescanState::Scanning { progress, duration }) = rescan_state(monitor) {
+            last_progress = progress;
+            info!("Rescan {:.0}% done after {duration}s", progress * 100.0);
+        }
+    }
+
+    match handle.join() {
+        Ok(Ok((start_height, stop_height))) => Ok(RescanOutcome::Completed {
+            start_height,
+            stop_height,
+        }),
+        Ok(Err(e)) if e.to_string().contains("aborted") => Ok(RescanOutcome::Aborted {
+            progress: last_progress,
+        }),
+        // The worker's connection timed out or dropped, but the node scans on.
+        // Report that rather than an error --attempts would retry into a
+        // second, overlapping rescan.
+        Ok(Err(e)) => match rescan_state(monitor) {
+            Ok(RescanState::Scanning { progress, .. }) => {
+                Err(AppError::RescanStillRunning { progress })
+            }
+            _ => Err(e),
+        },
+        Err(panic) => std::panic::resume_unwind(panic),
+    }
+}
+
+// Abort a rescan running in another process, returning how far it got, or
+// `None` when the wallet was not rescanning
+pub fn abort_rescan(rpc: &Client) -> Result<Option<f32>, AppError> {
+    let RescanState::Scanning { progress, .. } = rescan_state(rpc)? else {
+        return Ok(None);
+    };
+    let aborted: bool = rpc.call("abortrescan", &[])?;
+    // The rescan may have finished between the two calls
+    Ok(aborted.then_some(progress))
+}
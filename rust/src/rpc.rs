@@ -0,0 +1,265 @@
+// Node connections that count every RPC round-trip they make and remember
+// the last request, so a failure can always name the call that caused it
+use bitcoincore_rpc::jsonrpc::simple_http::SimpleHttpTransport;
+use bitcoincore_rpc::jsonrpc::{self, Request, Response, Transport};
+use bitcoincore_rpc::Client;
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+// Round-trips a default run (no flags) must stay within, and fails past: 203
+// on a fresh chain for mining the first coinbase to maturity (one getbalance,
+// then a generate and a getbalance per block), plus about 45 for setup, the
+// send and the analysis
+pub const BASELINE_CALL_BUDGET: usize = 300;
+
+// Round-trips made by every client created through `connect`
+static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+// A request as sent: the RPC method and each argument's JSON
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SentRequest {
+    pub method: String,
+    pub params: Vec<String>,
+}
+
+thread_local! {
+    // The request this thread sent most recently, kept only while it failed.
+    // RPC errors must be converted to AppError on the thread that made the
+    // call, before it makes another one.
+    static LAST_REQUEST: RefCell<Option<SentRequest>> = const { RefCell::new(None) };
+}
+
+// Take the request this thread sent last, if it has not been taken yet
+pub fn take_last_request() -> Option<SentRequest> {
+    LAST_REQUEST.with(|last| last.borrow_mut().take())
+}
+
+// Transport that bumps `calls` before each request goes out
+struct CountingTransport<T> {
+    inner: T,
+    calls: &'static AtomicUsize,
+}
+
+impl<T: Transport> Transport for CountingTransport<T> {
+    fn send_request(&self, request: Request) -> Result<Response, jsonrpc::Error> {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        let sent = SentRequest {
+            method: request.method.to_string(),
+            params: request.params.iter().map(|p| p.get().to_string()).collect(),
+        };
+        let response = self.inner.send_request(request);
+        // A successful call leaves nothing behind for errors raised after it
+        let failed = !matches!(&response, Ok(r) if r.error.is_none());
+        LAST_REQUEST.with(|last| *last.borrow_mut() = failed.then_some(sent));
+        response
+    }
+
+    fn send_batch(&self, requests: &[Request]) -> Result<Vec<Response>, jsonrpc::Error> {
+        // A batch is one round-trip however many requests it carries
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        LAST_REQUEST.with(|last| *last.borrow_mut() = None);
+        self.inner.send_batch(requests)
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.fmt_target(f)
+    }
+}
+
+// How long an ordinary call may take before the transport gives up
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(15);
+
+// For calls that block until the node finishes, like rescanblockchain over a
+// long chain, which can take hours
+pub const LONG_CALL_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+// Connect to `url` with user/password auth, counting the calls made. A call
+// that takes longer than `timeout` fails with a transport error, although the
+// node keeps working on it.
+pub fn connect(
+    url: &str,
+    user: &str,
+    pass: &str,
+    timeout: Duration,
+) -> bitcoincore_rpc::Result<Client> {
+    let transport = SimpleHttpTransport::builder()
+        .url(url)
+        .map_err(|e| bitcoincore_rpc::Error::JsonRpc(e.into()))?
+        .auth(user, Some(pass))
+        .timeout(timeout)
+        .build();
+    Ok(Client::from_jsonrpc(jsonrpc::Client::with_transport(
+        CountingTransport {
+            inner: transport,
+            calls: &CALLS,
+        },
+    )))
+}
+
+// RPC round-trips made so far in this run
+pub fn rpc_call_count() -> usize {
+    CALLS.load(Ordering::Relaxed)
+}
+
+// Start counting from zero, e.g. for another --attempts retry
+pub fn reset_call_count() {
+    CALLS.store(0, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AppError;
+    use bitcoincore_rpc::RpcApi;
+
+    // Answers every request with a null result and never touches the network
+    struct StubTransport;
+
+    impl StubTransport {
+        fn respond(request: &Request) -> Response {
+            Response {
+                result: Some(serde_json::value::RawValue::from_string("null".into()).unwrap()),
+                error: None,
+                id: request.id.clone(),
+                jsonrpc: Some("2.0".to_string()),
+            }
+        }
+    }
+
+    impl Transport for StubTransport {
+        fn send_request(&self, request: Request) -> Result<Response, jsonrpc::Error> {
+            Ok(Self::respond(&request))
+        }
+
+        fn send_batch(&self, requests: &[Request]) -> Result<Vec<Response>, jsonrpc::Error> {
+            Ok(requests.iter().map(Self::respond).collect())
+        }
+
+        fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "stub")
+        }
+    }
+
+    // Rejects every request the way the node rejects an unknown txid
+    struct FailingTransport;
+
+    impl Transport for FailingTransport {
+        fn send_request(&self, request: Request) -> Result<Response, jsonrpc::Error> {
+            Ok(Response {
+                result: None,
+                error: Some(jsonrpc::error::RpcError {
+                    code: -5,
+                    message: "No such mempool or blockchain transaction".to_string(),
+                    data: None,
+                }),
+                id: request.id.clone(),
+                jsonrpc: Some("2.0".to_string()),
+            })
+        }
+
+        fn send_batch(&self, requests: &[Request]) -> Result<Vec<Response>, jsonrpc::Error> {
+            requests
+                .iter()
+                .map(|r| self.send_request(r.clone()))
+                .collect()
+        }
+
+        fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "failing")
+        }
+    }
+
+    fn failing(calls: &'static AtomicUsize) -> Client {
+        Client::from_jsonrpc(jsonrpc::Client::with_transport(CountingTransport {
+            inner: FailingTransport,
+            calls,
+        }))
+    }
+
+    fn counting(calls: &'static AtomicUsize) -> jsonrpc::Client {
+        jsonrpc::Client::with_transport(CountingTransport {
+            inner: StubTransport,
+            calls,
+        })
+    }
+
+    #[test]
+    fn counts_one_per_request() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        let client = Client::from_jsonrpc(counting(&CALLS));
+        for _ in 0..3 {
+            let _ = client.call::<serde_json::Value>("getblockcount", &[]);
+        }
+        assert_eq!(CALLS.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn counts_one_per_batch() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        let client = counting(&CALLS);
+        let requests: Vec<_> = (0..5)
+            .map(|_| client.build_request("getblockcount", &[]))
+            .collect();
+        client.send_batch(&requests).unwrap();
+        assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn errors_name_the_failing_call() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        let client = failing(&CALLS);
+        let err: AppError = client
+            .call::<serde_json::Value>("getrawtransaction", &[serde_json::json!("ab")])
+            .unwrap_err()
+            .into();
+        match err {
+            AppError::RpcCall { method, params, .. } => {
+                assert_eq!(method, "getrawtransaction");
+                assert_eq!(params, "\"ab\"");
+            }
+            other => panic!("expected RpcCall, got {other:?}"),
+        }
+        // The record is consumed, so an unrelated error carries no stale context
+        assert!(take_last_request().is_none());
+    }
+
+    #[test]
+    fn secret_arguments_are_redacted() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        let client = failing(&CALLS);
+        let err: AppError = client
+            .call::<serde_json::Value>("walletpassphrase", &[serde_json::json!("hunter2")])
+            .unwrap_err()
+            .into();
+        assert!(!err.to_string().contains("hunter2"));
+        assert!(err.to_string().contains("walletpassphrase(<redacted>)"));
+    }
+
+    #[test]
+    fn errors_after_a_successful_call_carry_no_context() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        let failing = failing(&CALLS);
+        let _ = failing.call::<serde_json::Value>("getrawtransaction", &[]);
+        let client = Client::from_jsonrpc(counting(&CALLS));
+        client
+            .call::<serde_json::Value>("getblockcount", &[])
+            .unwrap();
+        let err: AppError = bitcoincore_rpc::Error::UnexpectedStructure.into();
+        assert!(matches!(err, AppError::Rpc(_)), "got {err:?}");
+    }
+
+    #[test]
+    fn errors_converted_on_another_thread_carry_no_context() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        let client = failing(&CALLS);
+        let e = client
+            .call::<serde_json::Value>("rescanblockchain", &[])
+            .unwrap_err();
+        let err = std::thread::spawn(move || AppError::from(e))
+            .join()
+            .unwrap();
+        assert!(matches!(err, AppError::Rpc(_)), "got {err:?}");
+    }
+}
@@ -0,0 +1,120 @@
+// Phase timings exported as OpenTelemetry spans over OTLP/HTTP (protobuf),
+// recorded with `tracing` and bridged by tracing-opentelemetry.
+// Only built with the `otel` feature; otherwise every call is a no-op, so
+// default builds neither record nor send anything.
+
+#[cfg(feature = "otel")]
+pub use otel::{finish_run, span, start_run};
+
+#[cfg(not(feature = "otel"))]
+pub use noop::{finish_run, span, start_run};
+
+#[cfg(not(feature = "otel"))]
+mod noop {
+    pub struct Span;
+
+    impl Span {
+        pub fn attr(&mut self, _key: &str, _value: impl ToString) {}
+        pub fn end(self) {}
+    }
+
+    pub fn start_run() {}
+
+    pub fn span(_name: &'static str) -> Span {
+        Span
+    }
+
+    pub fn finish_run(_ok: bool) {}
+}
+
+#[cfg(feature = "otel")]
+mod otel {
+    use crate::logging::warning;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_otlp::SpanExporter;
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use opentelemetry_sdk::Resource;
+    use std::sync::{Mutex, OnceLock};
+    use tracing::field::Empty;
+    use tracing::span::EnteredSpan;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    // Exports finished spans; kept so finish_run can flush it
+    static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+    // The run every phase span belongs to, closed by finish_run
+    static RUN: Mutex<Option<tracing::Span>> = Mutex::new(None);
+
+    // A timed phase of the run, recorded when ended or dropped
+    pub struct Span {
+        span: EnteredSpan,
+    }
+
+    impl Span {
+        // Only the fields declared in `span` below are kept
+        pub fn attr(&mut self, key: &str, value: impl ToString) {
+            self.span.record(key, value.to_string().as_str());
+        }
+
+        pub fn end(self) {}
+    }
+
+    // Install a tracing subscriber that sends spans over OTLP/HTTP to
+    // OTEL_EXPORTER_OTLP_ENDPOINT, then open the run span.
+    // Export problems are reported but never fail the run.
+    pub fn start_run() {
+        let exporter = match SpanExporter::builder().with_http().build() {
+            Ok(exporter) => exporter,
+            Err(e) => {
+                warning!("could not set up span export: {e}");
+                return;
+            }
+        };
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(
+                Resource::builder()
+                    .with_service_name(env!("CARGO_PKG_NAME"))
+                    .build(),
+            )
+            .build();
+        let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("phases"));
+        if tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))
+            .is_err()
+        {
+            warning!("a tracing subscriber is already installed; spans are not exported");
+            return;
+        }
+        let _ = PROVIDER.set(provider);
+        if let Ok(mut run) = RUN.lock() {
+            *run = Some(tracing::info_span!("run", otel.status_code = Empty));
+        }
+    }
+
+    pub fn span(name: &'static str) -> Span {
+        let parent = RUN.lock().ok().and_then(|run| run.clone());
+        let span = tracing::info_span!(
+            parent: parent.as_ref().and_then(tracing::Span::id),
+            "phase",
+            otel.name = name,
+            blocks = Empty,
+            txid = Empty,
+            block_height = Empty,
+            fee = Empty,
+        );
+        Span {
+            span: span.entered(),
+        }
+    }
+
+    // Close the run span and flush it with every phase span to the collector
+    pub fn finish_run(ok: bool) {
+        if let Some(run) = RUN.lock().ok().and_then(|mut run| run.take()) {
+            run.record("otel.status_code", if ok { "ok" } else { "error" });
+        }
+        if let Some(provider) = PROVIDER.get() {
+            if let Err(e) = provider.shutdown() {
+                warning!("could not export spans: {e}");
+            }
+        }
+    }
+}
@@ -0,0 +1,333 @@
+// Helpers that operate on one or more wallet clients
+use crate::amount::{self, AmountFormatter};
+use crate::error::AppError;
+use crate::logging::{info, warning};
+use bitcoincore_rpc::bitcoin::{Address, Amount, Txid};
+use bitcoincore_rpc::{Client, RpcApi};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::sync::Mutex;
+
+// Query the balance of every named wallet client
+pub fn all_balances(clients: &[(&str, &Client)]) -> Result<HashMap<String, Amount>, AppError> {
+    let mut balances = HashMap::with_capacity(clients.len());
+    for (name, client) in clients {
+        balances.insert(name.to_string(), client.get_balance(None, None)?);
+    }
+    Ok(balances)
+}
+
+// Print balances as a two-column table, sorted by wallet name
+pub fn print_balances(
+    title: &str,
+    balances: &HashMap<String, Amount>,
+    formatter: &dyn AmountFormatter,
+) {
+    let mut names: Vec<_> = balances.keys().collect();
+    names.sort();
+    let width = names
+        .iter()
+        .map(|n| n.len())
+        .max()
+        .unwrap_or(0)
+        .max("Wallet".len());
+
+    info!("{title}");
+    info!("  {:<width$}  {:>17}", "Wallet", "Balance (BTC)");
+    for name in names {
+        info!(
+            "  {:<width$}  {:>17}",
+            name,
+            formatter.format(balances[name])
+        );
+    }
+}
+
+// Label the node gives addresses created without one
+pub const DEFAULT_LABEL: &str = "";
+
+#[derive(Deserialize)]
+struct ReceivedByLabel {
+    label: String,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    amount: Amount,
+}
+
+// Confirmed amount received per address label, e.g. "Mining Reward" and
+// "Payment". Addresses without a label are grouped under DEFAULT_LABEL.
+pub fn received_by_label(rpc: &Client) -> Result<BTreeMap<String, Amount>, AppError> {
+    let rows: Vec<ReceivedByLabel> = rpc.call("listreceivedbylabel", &[])?;
+    let mut totals = BTreeMap::new();
+    for row in rows {
+        *totals.entry(row.label).or_insert(Amount::ZERO) += row.amount;
+    }
+    Ok(totals)
+}
+
+// Print per-label totals, showing the default label as "(no label)"
+pub fn print_received_by_label(wallet: &str, totals: &BTreeMap<String, Amount>) {
+    info!("{wallet} received by label:");
+    if totals.is_empty() {
+        info!("  (nothing received)");
+    }
+    for (label, amount) in totals {
+        let label = if label == DEFAULT_LABEL {
+            "(no label)"
+        } else {
+            label
+        };
+        info!("  {label:<20} {:>17} BTC", amount::to_btc_string(*amount));
+    }
+}
+
+// Trusted balance next to the UTXOs that make it up
+#[derive(Debug, Clone, Copy)]
+pub struct Reconciliation {
+    pub trusted: Amount,
+    pub utxo_total: Amount,
+    /// Included in getbalances but not spendable yet, so not in the UTXO sum
+    pub immature: Amount,
+    pub untrusted_pending: Amount,
+}
+
+// Check the wallet's trusted balance against the sum of its safe UTXOs.
+// listunspent leaves out immature coinbase outputs and, with include_unsafe
+// off, unconfirmed coins from others, which are exactly the coins getbalances
+// reports outside `trusted`. Locked coins also count towards the balance but
+// are hidden from listunspent, so they show up as a discrepancy.
+pub fn reconcile_balance(rpc: &Client, tolerance: Amount) -> Result<Reconciliation, AppError> {
+    let balances = rpc.get_balances()?.mine;
+    let utxo_total = rpc
+        .list_unspent(Some(0), None, None, Some(false), None)?
+        .iter()
+        .map(|utxo| utxo.amount)
+        .sum();
+    let reconciliation = Reconciliation {
+        trusted: balances.trusted,
+        utxo_total,
+        immature: balances.immature,
+        untrusted_pending: balances.untrusted_pending,
+    };
+
+    let difference = if balances.trusted > utxo_total {
+        balances.trusted - utxo_total
+    } else {
+        utxo_total - balances.trusted
+    };
+    if difference > tolerance {
+        return Err(AppError::BalanceMismatch(format!(
+            "trusted balance {} BTC but safe UTXOs total {} BTC",
+            amount::to_btc_string(balances.trusted),
+            amount::to_btc_string(utxo_total)
+        )));
+    }
+    Ok(reconciliation)
+}
+
+// Check the wallet's confirmed balance is `expected`, give or take
+// `tolerance`, returning the actual balance
+pub fn assert_balance(
+    rpc: &Client,
+    expected: Amount,
+    tolerance: Amount,
+) -> Result<Amount, AppError> {
+    let actual = rpc.get_balance(Some(1), None)?;
+    let difference = if actual > expected {
+        actual - expected
+    } else {
+        expected - actual
+    };
+    if difference > tolerance {
+        return Err(AppError::BalanceMismatch(format!(
+            "confirmed balance is {} BTC, expected {} BTC",
+            amount::to_btc_string(actual),
+            amount::to_btc_string(expected)
+        )));
+    }
+    Ok(actual)
+}
+
+// Where gettxout found an unspent output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputLocation {
+    /// Unspent in a transaction still waiting in the mempool
+    Mempool,
+    /// Unspent in a confirmed transaction
+    Chain { confirmations: u32 },
+    /// Spent, unknown, or unconfirmed while mempool outputs were excluded
+    NotFound,
+}
+
+impl fmt::Display for OutputLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputLocation::Mempool => write!(f, "found in the mempool (unconfirmed)"),
+            OutputLocation::Chain { confirmations } => {
+                write!(f, "found in the chain ({confirmations} confirmation(s))")
+            }
+            OutputLocation::NotFound => write!(f, "not found"),
+        }
+    }
+}
+
+// Find the output of the wallet's transaction `txid` that pays `address` and
+// look it up with gettxout, counting unconfirmed outputs only when
+// `include_mempool` is set. None when the transaction pays nothing to `address`.
+pub fn locate_output(
+    rpc: &Client,
+    txid: &Txid,
+    address: &Address,
+    include_mempool: bool,
+) -> Result<Option<(u32, OutputLocation)>, AppError> {
+    let tx = rpc.get_transaction(txid, None)?;
+    let Some(vout) = tx.details.iter().find_map(|detail| {
+        let paid = detail.address.as_ref()?.clone().assume_checked();
+        (paid == *address).then_some(detail.vout)
+    }) else {
+        return Ok(None);
+    };
+    let location = match rpc.get_tx_out(txid, vout, Some(include_mempool))? {
+        Some(out) if out.confirmations == 0 => OutputLocation::Mempool,
+        Some(out) => OutputLocation::Chain {
+            confirmations: out.confirmations,
+        },
+        None => OutputLocation::NotFound,
+    };
+    Ok(Some((vout, location)))
+}
+
+// Whether the wallet behind `rpc` owns `address`; unparseable addresses and
+// failed lookups count as not owned
+pub fn owns_address(rpc: &Client, address: &str) -> bool {
+    address.parse::<Address<_>>().is_ok_and(|a| {
+        rpc.get_address_info(&a.assume_checked())
+            .is_ok_and(|info| info.is_mine.unwrap_or(false))
+    })
+}
+
+// Custom RPC call for 'sendmany', paying several recipients in one transaction.
+// Recipients listed in `subtract_fee_from` (by index) share the fee between them.
+pub fn send_many(
+    rpc: &Client,
+    recipients: &[(String, Amount)],
+    subtract_fee_from: &[usize],
+) -> bitcoincore_rpc::Result<Txid> {
+    let amounts: serde_json::Map<_, _> = recipients
+        .iter()
+        .map(|(address, amount)| (address.clone(), amount::to_rpc_value(*amount)))
+        .collect();
+    let subtract: Vec<_> = subtract_fee_from
+        .iter()
+        .map(|&i| recipients[i].0.clone())
+        .collect();
+    let params = [
+        json!(""),       // Dummy account name, must be empty
+        json!(amounts),  // Address -> amount in BTC
+        json!(null),     // Minimum confirmations (default)
+        json!(null),     // Comment (none)
+        json!(subtract), // Addresses the fee is deducted from
+    ];
+    rpc.call("sendmany", &params)
+}
+
+// Addresses paid so far in this process, across every iteration of the flow
+static PAID: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+// Refuse to pay an address already paid in this run, or listed twice in
+// `addresses`, unless `allow_reuse` is set, in which case it only warns.
+// Nothing is recorded here, so a send that fails before broadcasting can be
+// retried with the same addresses.
+pub fn check_recipients(addresses: &[String], allow_reuse: bool) -> Result<(), AppError> {
+    let paid = PAID.lock().unwrap_or_else(|e| e.into_inner());
+    let mut seen = HashSet::new();
+    for address in addresses {
+        let reused = paid.as_ref().is_some_and(|p| p.contains(address));
+        if !seen.insert(address) || reused {
+            if !allow_reuse {
+                return Err(AppError::AddressReuse(address.to_string()));
+            }
+            warning!(
+                "{address} was already paid in this run; reusing it as --allow-reuse was given"
+            );
+        }
+    }
+    Ok(())
+}
+
+// Remember `addresses` as paid, once the payment to them is broadcast
+pub fn record_recipients(addresses: &[String]) {
+    let mut paid = PAID.lock().unwrap_or_else(|e| e.into_inner());
+    paid.get_or_insert_with(HashSet::new)
+        .extend(addresses.iter().cloned());
+}
+
+// Optional arguments of 'sendtoaddress', set by name instead of as a row of
+// positional `None`s. Unset arguments go to the node as null, and trailing
+// ones are left off so older nodes that lack them still accept the call.
+#[derive(Debug, Clone, Default)]
+pub struct SendToAddressOptions {
+    comment: Option<String>,
+    comment_to: Option<String>,
+    subtract_fee: Option<bool>,
+    /// sat/vB; Core 0.21+
+    fee_rate: Option<f64>,
+}
+
+impl SendToAddressOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn comment(mut self, comment: &str) -> Self {
+        self.comment = Some(comment.to_string());
+        self
+    }
+
+    pub fn comment_to(mut self, comment_to: &str) -> Self {
+        self.comment_to = Some(comment_to.to_string());
+        self
+    }
+
+    pub fn subtract_fee(mut self, subtract_fee: bool) -> Self {
+        self.subtract_fee = Some(subtract_fee);
+        self
+    }
+
+    pub fn fee_rate(mut self, sat_per_vb: f64) -> Self {
+        self.fee_rate = Some(sat_per_vb);
+        self
+    }
+
+    // Positional params after address and amount, in the order Core expects
+    fn params(&self) -> Vec<serde_json::Value> {
+        let mut params = vec![
+            json!(self.comment),
+            json!(self.comment_to),
+            json!(self.subtract_fee),
+            // replaceable, conf_target, estimate_mode and avoid_reuse keep
+            // the wallet's defaults
+            json!(null),
+            json!(null),
+            json!(null),
+            json!(null),
+            json!(self.fee_rate),
+        ];
+        while params.last().is_some_and(serde_json::Value::is_null) {
+            params.pop();
+        }
+        params
+    }
+
+    pub fn send(
+        &self,
+        rpc: &Client,
+        address: &Address,
+        value: Amount,
+    ) -> bitcoincore_rpc::Result<Txid> {
+        let mut params = vec![json!(address.to_string()), amount::to_rpc_value(value)];
+        params.extend(self.params());
+        rpc.call("sendtoaddress", &params)
+    }
+}
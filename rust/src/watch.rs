@@ -0,0 +1,117 @@
+// Long-running wallet monitor: poll listsinceblock and print wallet
+// transactions as they appear and confirm, until Ctrl-C
+use crate::amount;
+use crate::error::AppError;
+use crate::logging::{info, warning};
+use bitcoincore_rpc::bitcoin::{SignedAmount, Txid};
+use bitcoincore_rpc::json::ListTransactionResult;
+use bitcoincore_rpc::{Client, RpcApi};
+use std::collections::HashMap;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Poll interval used when --watch-interval is not given, in seconds
+pub const DEFAULT_INTERVAL_SECS: u64 = 5;
+
+// How often the sleep between polls checks for Ctrl-C
+const INTERRUPT_CHECK: Duration = Duration::from_millis(100);
+
+// Print wallet activity from the current tip onward, every `interval`. The
+// checkpoint moves to each poll's last block; unconfirmed entries come back on
+// every poll, so each one is printed when first seen and again once it confirms.
+pub fn watch(rpc: &Client, interval: Duration) -> Result<(), AppError> {
+    interrupt::install();
+    let mut checkpoint = rpc.get_best_block_hash()?;
+    // Confirmations last printed per wallet entry (txid, vout, category)
+    let mut seen: HashMap<(Txid, u32, String), i32> = HashMap::new();
+    info!("Watching for new transactions from block {checkpoint}, Ctrl-C to stop");
+
+    while !interrupt::interrupted() {
+        let result = rpc.list_since_block(Some(&checkpoint), None, None, Some(true))?;
+        for entry in &result.removed {
+            if seen.remove(&entry_key(entry)).is_some() {
+                warning!("{} was reorganised out of the chain", entry.info.txid);
+            }
+        }
+        for entry in &result.transactions {
+            let confirmations = entry.info.confirmations;
+            let previous = seen.insert(entry_key(entry), confirmations);
+            let newly_confirmed = previous.is_some_and(|before| before <= 0 && confirmations > 0);
+            if previous.is_none() || newly_confirmed {
+                print_entry(entry);
+            }
+        }
+        checkpoint = result.lastblock;
+
+        let until = Instant::now() + interval;
+        while Instant::now() < until && !interrupt::interrupted() {
+            thread::sleep(INTERRUPT_CHECK);
+        }
+    }
+    info!("Stopped watching");
+    Ok(())
+}
+
+fn entry_key(entry: &ListTransactionResult) -> (Txid, u32, String) {
+    (
+        entry.info.txid,
+        entry.detail.vout,
+        format!("{:?}", entry.detail.category),
+    )
+}
+
+fn print_entry(entry: &ListTransactionResult) {
+    info!(
+        "  {} {:?} {} BTC, {} confirmation(s)",
+        entry.info.txid,
+        entry.detail.category,
+        signed_btc(entry.detail.amount),
+        entry.info.confirmations
+    );
+}
+
+// "+1.00000000" for credits, "-1.00000000" for debits
+fn signed_btc(value: SignedAmount) -> String {
+    let sign = if value.is_negative() { "-" } else { "+" };
+    format!("{sign}{}", amount::to_btc_string(amount::magnitude(value)))
+}
+
+// Ctrl-C sets a flag the poll loop checks, so the watch ends between polls
+// instead of the process dying mid-request
+#[cfg(unix)]
+mod interrupt {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    const SIGINT: i32 = 2;
+
+    static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    }
+
+    extern "C" fn on_sigint(_signum: i32) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+    }
+
+    pub fn install() {
+        // SAFETY: the handler only stores to an atomic, which is signal-safe
+        unsafe {
+            signal(SIGINT, on_sigint);
+        }
+    }
+
+    pub fn interrupted() -> bool {
+        INTERRUPTED.load(Ordering::SeqCst)
+    }
+}
+
+// Elsewhere Ctrl-C keeps its default behaviour of ending the process
+#[cfg(not(unix))]
+mod interrupt {
+    pub fn install() {}
+
+    pub fn interrupted() -> bool {
+        false
+    }
+}